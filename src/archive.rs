@@ -0,0 +1,986 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use symphonia::core::io::MediaSource;
+
+const SEPARATOR: char = '!';
+const END_OF_DIRECTORY: u32 = 0x06054b50;
+const DIRECTORY_ENTRY: u32 = 0x02014b50;
+const LOCAL_HEADER: u32 = 0x04034b50;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+const ENCRYPTED: u16 = 1;
+/// Compressed members are inflated into memory, so anything claiming to be bigger than this is refused
+const MAX_INFLATED_SIZE: u64 = 1 << 30;
+/// How many archives' directories to remember between opening members
+const LISTING_CACHE_SIZE: usize = 16;
+
+type Listing = (Option<SystemTime>, u64, Arc<Vec<Member>>);
+
+static LISTINGS: Mutex<BTreeMap<PathBuf, Listing>> = Mutex::new(BTreeMap::new());
+
+pub struct Member {
+    pub name: String,
+    pub path: PathBuf,
+    method: u16,
+    encrypted: bool,
+    compressed_size: u64,
+    size: u64,
+    crc: u32,
+    header_offset: u64,
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+}
+
+/// Members are named like `album.zip!/03 Track.flac`; the directory prefix covering them all is `album.zip!`
+pub fn members_root(archive: &Path) -> PathBuf {
+    let mut root = archive.as_os_str().to_owned();
+    root.push(SEPARATOR.to_string());
+    PathBuf::from(root)
+}
+
+/// Splits a library path into the archive that holds it and the name of the member, if it is inside one
+pub fn split(path: &Path) -> Option<(PathBuf, String)> {
+    let text = path.to_str()?;
+    let mut search = 0;
+    while let Some(index) = text[search..].find("!/").map(|index| index + search) {
+        let archive = Path::new(&text[..index]);
+        if is_archive(archive) {
+            return Some((archive.to_path_buf(), text[index + 2..].to_string()));
+        }
+        search = index + 2;
+    }
+    None
+}
+
+/// Opens a file for decoding, reading it out of an archive if necessary
+pub fn open(path: &Path) -> Result<Box<dyn MediaSource>> {
+    match split(path) {
+        None => Ok(Box::new(File::open(path)?)),
+        Some((archive, name)) => cached_list(&archive)?
+            .iter()
+            .find(|member| member.name == name)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no such file in archive"))?
+            .open(&archive),
+    }
+}
+
+/// Lists an archive, reusing the last listing if the archive hasn't changed since
+fn cached_list(archive: &Path) -> Result<Arc<Vec<Member>>> {
+    let metadata = std::fs::metadata(archive)?;
+    let (modified, length) = (metadata.modified().ok(), metadata.len());
+    let mut listings = LISTINGS.lock().expect("Failed to unlock archive listings");
+    if let Some((cached_modified, cached_length, members)) = listings.get(archive)
+        && *cached_modified == modified
+        && *cached_length == length
+    {
+        return Ok(members.clone());
+    }
+    let members = Arc::new(list(archive)?);
+    if listings.len() >= LISTING_CACHE_SIZE {
+        listings.clear();
+    }
+    listings.insert(archive.to_path_buf(), (modified, length, members.clone()));
+    Ok(members)
+}
+
+pub fn list(archive: &Path) -> Result<Vec<Member>> {
+    let mut file = File::open(archive)?;
+    let length = file.metadata()?.len();
+    // The end of central directory record is at least 22 bytes, followed by a comment of at most 64KiB
+    let tail_length = length.min(22 + u16::MAX as u64);
+    file.seek(SeekFrom::Start(length - tail_length))?;
+    let mut tail = vec![0; tail_length as usize];
+    file.read_exact(&mut tail)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&index| read_u32(&tail, index) == Some(END_OF_DIRECTORY))
+        .ok_or_else(|| invalid("missing end of central directory"))?;
+    let count = read_u16(&tail, end + 10).ok_or_else(|| invalid("truncated directory"))?;
+    let directory_size = read_u32(&tail, end + 12).ok_or_else(|| invalid("truncated directory"))?;
+    let directory_offset =
+        read_u32(&tail, end + 16).ok_or_else(|| invalid("truncated directory"))?;
+    if directory_offset == u32::MAX || count == u16::MAX {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "ZIP64 archives are not supported",
+        ));
+    }
+    if directory_offset as u64 + directory_size as u64 > length {
+        return Err(invalid("central directory is past the end of the file"));
+    }
+    file.seek(SeekFrom::Start(directory_offset as u64))?;
+    let mut directory = vec![0; directory_size as usize];
+    file.read_exact(&mut directory)?;
+    let root = members_root(archive);
+    let mut members = Vec::new();
+    let mut position = 0;
+    for _ in 0..count {
+        let field = |offset: usize| read_u16(&directory, position + offset);
+        if read_u32(&directory, position) != Some(DIRECTORY_ENTRY) {
+            return Err(invalid("bad central directory entry"));
+        }
+        let (
+            Some(flags),
+            Some(method),
+            Some(crc),
+            Some(compressed_size),
+            Some(size),
+            Some(name_length),
+            Some(extra_length),
+            Some(comment_length),
+            Some(header_offset),
+        ) = (
+            field(8),
+            field(10),
+            read_u32(&directory, position + 16),
+            read_u32(&directory, position + 20),
+            read_u32(&directory, position + 24),
+            field(28),
+            field(30),
+            field(32),
+            read_u32(&directory, position + 42),
+        )
+        else {
+            return Err(invalid("truncated directory"));
+        };
+        let name_start = position + 46;
+        let name = directory
+            .get(name_start..name_start + name_length as usize)
+            .ok_or_else(|| invalid("truncated directory"))?;
+        let name = String::from_utf8_lossy(name).replace('\\', "/");
+        position =
+            name_start + name_length as usize + extra_length as usize + comment_length as usize;
+        if name.ends_with('/') || name.split('/').any(|part| part == "..") {
+            continue;
+        }
+        members.push(Member {
+            path: root.join(&name),
+            name,
+            method,
+            encrypted: flags & ENCRYPTED != 0,
+            compressed_size: compressed_size as u64,
+            size: size as u64,
+            crc,
+            header_offset: header_offset as u64,
+        });
+    }
+    Ok(members)
+}
+
+impl Member {
+    /// The size of the member once extracted
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    pub fn open(&self, archive: &Path) -> Result<Box<dyn MediaSource>> {
+        if self.encrypted {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "encrypted members are not supported",
+            ));
+        }
+        let mut file = File::open(archive)?;
+        let length = file.metadata()?.len();
+        file.seek(SeekFrom::Start(self.header_offset))?;
+        let mut header = [0; 30];
+        file.read_exact(&mut header)?;
+        if read_u32(&header, 0) != Some(LOCAL_HEADER) {
+            return Err(invalid("bad local header"));
+        }
+        let start = self.header_offset
+            + 30
+            + read_u16(&header, 26).unwrap_or(0) as u64
+            + read_u16(&header, 28).unwrap_or(0) as u64;
+        let available = length.saturating_sub(start);
+        match self.method {
+            STORED if self.size <= available => Ok(Box::new(StoredMember {
+                file,
+                start,
+                length: self.size,
+                position: 0,
+                crc: Crc::new(self.crc, self.size),
+            })),
+            DEFLATED if self.compressed_size <= available && self.size <= MAX_INFLATED_SIZE => {
+                file.seek(SeekFrom::Start(start))?;
+                let mut compressed = Vec::with_capacity(self.compressed_size as usize);
+                file.take(self.compressed_size)
+                    .read_to_end(&mut compressed)?;
+                Ok(Box::new(DeflatedMember {
+                    inflater: Inflater::new(compressed, self.size as usize, self.crc),
+                    position: 0,
+                }))
+            }
+            STORED | DEFLATED => Err(invalid("member is larger than the archive allows")),
+            method => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("unsupported compression method {}", method),
+            )),
+        }
+    }
+}
+
+struct StoredMember {
+    file: File,
+    start: u64,
+    length: u64,
+    position: u64,
+    crc: Crc,
+}
+
+impl Read for StoredMember {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let remaining = self.length.saturating_sub(self.position);
+        let size = (buf.len() as u64).min(remaining) as usize;
+        if size == 0 {
+            self.crc.check()?;
+            return Ok(0);
+        }
+        self.file
+            .seek(SeekFrom::Start(self.start + self.position))?;
+        let read = self.file.read(&mut buf[..size])?;
+        self.crc.update(self.position, &buf[..read])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for StoredMember {
+    fn seek(&mut self, position: SeekFrom) -> Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        }
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "seek before start of file"))?;
+        self.position = position;
+        Ok(position)
+    }
+}
+
+impl MediaSource for StoredMember {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.length)
+    }
+}
+
+/// A compressed member, only inflated as far as it has been read
+struct DeflatedMember {
+    inflater: Inflater,
+    position: u64,
+}
+
+impl Read for DeflatedMember {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let start = self.position.min(self.inflater.size as u64) as usize;
+        let end = (start + buf.len()).min(self.inflater.size);
+        self.inflater.fill(end)?;
+        let available = &self.inflater.output[start..end.min(self.inflater.output.len())];
+        buf[..available.len()].copy_from_slice(available);
+        self.position += available.len() as u64;
+        Ok(available.len())
+    }
+}
+
+impl Seek for DeflatedMember {
+    fn seek(&mut self, position: SeekFrom) -> Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => (self.inflater.size as u64).checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        }
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "seek before start of file"))?;
+        self.position = position;
+        Ok(position)
+    }
+}
+
+impl MediaSource for DeflatedMember {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.inflater.size as u64)
+    }
+}
+
+/// Checks a member against the CRC-32 its archive gives for it, as far as it has been read from the start
+struct Crc {
+    expected: u32,
+    length: u64,
+    crc: u32,
+    /// How many bytes from the start of the member have gone into the CRC so far
+    checked: u64,
+}
+
+/// The table for the CRC-32 polynomial used by ZIP
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xEDB88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+impl Crc {
+    fn new(expected: u32, length: u64) -> Self {
+        Crc {
+            expected,
+            length,
+            crc: 0,
+            checked: 0,
+        }
+    }
+    /// Takes in bytes read at this offset, failing once the whole member has been seen if it doesn't match
+    fn update(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let end = offset + data.len() as u64;
+        if offset <= self.checked && end > self.checked {
+            self.crc = crc32(self.crc, &data[(self.checked - offset) as usize..]);
+            self.checked = end;
+        }
+        self.check()
+    }
+    /// Fails from the moment the whole member is known not to match
+    fn check(&self) -> Result<()> {
+        if self.checked == self.length && self.crc != self.expected {
+            return Err(invalid("member is corrupt"));
+        }
+        Ok(())
+    }
+}
+
+/// Carries on a CRC-32 with more data
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn invalid(message: &'static str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct Bits {
+    data: Vec<u8>,
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits {
+    fn take(&mut self, count: u32) -> Result<u32> {
+        while self.count < count {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or_else(|| invalid("truncated compressed data"))?;
+            self.position += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u64 << count) - 1) as u32;
+        self.buffer = (self.buffer as u64 >> count) as u32;
+        self.count -= count;
+        Ok(value)
+    }
+}
+
+/// A canonical Huffman code as described in RFC 1951
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+    fn decode(&self, bits: &mut Bits) -> Result<u16> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for length in 1..16 {
+            code |= bits.take(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first {
+                return self
+                    .symbols
+                    .get((index + code - first) as usize)
+                    .copied()
+                    .ok_or_else(|| invalid("bad Huffman code"));
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(invalid("bad Huffman code"))
+    }
+}
+
+enum Block {
+    /// Between blocks, so the next thing is a block header
+    Header,
+    /// Part way through an uncompressed block with this many bytes left
+    Stored(usize),
+    /// Part way through a block compressed with these literal/length and distance codes
+    Compressed(Box<(Huffman, Huffman)>),
+    Done,
+}
+
+/// Decompresses DEFLATE data (RFC 1951) a piece at a time
+struct Inflater {
+    bits: Bits,
+    block: Block,
+    last: bool,
+    output: Vec<u8>,
+    /// How much output there should be; producing any more is an error
+    size: usize,
+    crc: Crc,
+}
+
+impl Inflater {
+    fn new(data: Vec<u8>, size: usize, crc: u32) -> Self {
+        Inflater {
+            bits: Bits {
+                data,
+                position: 0,
+                buffer: 0,
+                count: 0,
+            },
+            block: Block::Header,
+            last: false,
+            output: Vec::new(),
+            size,
+            crc: Crc::new(crc, size as u64),
+        }
+    }
+    /// Inflates until there are at least this many bytes of output or the data ends
+    fn fill(&mut self, wanted: usize) -> Result<()> {
+        let Inflater {
+            bits,
+            block,
+            last,
+            output,
+            size,
+            crc,
+        } = self;
+        crc.check()?;
+        while output.len() < wanted {
+            match block {
+                Block::Done => return Ok(()),
+                Block::Header if *last => {
+                    if output.len() != *size {
+                        return Err(invalid("member is smaller than it claims"));
+                    }
+                    *block = Block::Done;
+                }
+                Block::Header => {
+                    *last = bits.take(1)? == 1;
+                    *block = read_block_header(bits)?;
+                }
+                Block::Stored(remaining) => {
+                    let length = (*remaining).min(wanted - output.len());
+                    output.extend_from_slice(
+                        bits.data
+                            .get(bits.position..bits.position + length)
+                            .ok_or_else(|| invalid("truncated compressed data"))?,
+                    );
+                    bits.position += length;
+                    *remaining -= length;
+                    if *remaining == 0 {
+                        *block = Block::Header;
+                    }
+                }
+                Block::Compressed(codes) => {
+                    let (literals, distances) = &**codes;
+                    if !inflate_symbol(bits, output, literals, distances)? {
+                        *block = Block::Header;
+                    }
+                }
+            }
+            if output.len() > *size {
+                return Err(invalid("member is larger than it claims"));
+            }
+            if output.len() == *size {
+                crc.update(0, output)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_block_header(bits: &mut Bits) -> Result<Block> {
+    match bits.take(2)? {
+        0 => {
+            bits.buffer = 0;
+            bits.count = 0;
+            let header = bits
+                .data
+                .get(bits.position..bits.position + 4)
+                .ok_or_else(|| invalid("truncated compressed data"))?;
+            let length = u16::from_le_bytes([header[0], header[1]]);
+            if length != !u16::from_le_bytes([header[2], header[3]]) {
+                return Err(invalid("bad stored block length"));
+            }
+            bits.position += 4;
+            Ok(if length == 0 {
+                Block::Header
+            } else {
+                Block::Stored(length as usize)
+            })
+        }
+        1 => {
+            let mut lengths = [0u8; 288];
+            lengths[..144].fill(8);
+            lengths[144..256].fill(9);
+            lengths[256..280].fill(7);
+            lengths[280..].fill(8);
+            Ok(Block::Compressed(Box::new((
+                Huffman::new(&lengths),
+                Huffman::new(&[5; 30]),
+            ))))
+        }
+        2 => {
+            let literal_count = bits.take(5)? as usize + 257;
+            let distance_count = bits.take(5)? as usize + 1;
+            let code_length_count = bits.take(4)? as usize + 4;
+            let mut code_lengths = [0u8; 19];
+            for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+                code_lengths[index] = bits.take(3)? as u8;
+            }
+            let code_lengths = Huffman::new(&code_lengths);
+            let mut lengths = vec![0u8; literal_count + distance_count];
+            let mut index = 0;
+            while index < lengths.len() {
+                let (value, repeat) = match code_lengths.decode(bits)? {
+                    symbol @ 0..=15 => (symbol as u8, 1),
+                    16 => (
+                        *lengths[..index]
+                            .last()
+                            .ok_or_else(|| invalid("repeat with no previous length"))?,
+                        3 + bits.take(2)? as usize,
+                    ),
+                    17 => (0, 3 + bits.take(3)? as usize),
+                    _ => (0, 11 + bits.take(7)? as usize),
+                };
+                let end = index + repeat;
+                lengths
+                    .get_mut(index..end)
+                    .ok_or_else(|| invalid("too many code lengths"))?
+                    .fill(value);
+                index = end;
+            }
+            Ok(Block::Compressed(Box::new((
+                Huffman::new(&lengths[..literal_count]),
+                Huffman::new(&lengths[literal_count..]),
+            ))))
+        }
+        _ => Err(invalid("bad block type")),
+    }
+}
+
+/// Inflates one literal or copy, returning false at the end of the block
+fn inflate_symbol(
+    bits: &mut Bits,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<bool> {
+    let symbol = literals.decode(bits)? as usize;
+    match symbol {
+        0..=255 => output.push(symbol as u8),
+        256 => return Ok(false),
+        _ => {
+            let index = symbol - 257;
+            let length = *LENGTH_BASE
+                .get(index)
+                .ok_or_else(|| invalid("bad length code"))? as usize
+                + bits.take(LENGTH_EXTRA[index] as u32)? as usize;
+            let index = distances.decode(bits)? as usize;
+            let distance = *DISTANCE_BASE
+                .get(index)
+                .ok_or_else(|| invalid("bad distance code"))? as usize
+                + bits.take(DISTANCE_EXTRA[index] as u32)? as usize;
+            let start = output
+                .len()
+                .checked_sub(distance)
+                .ok_or_else(|| invalid("distance too far back"))?;
+            // The copy may overlap the bytes it is producing, so it must go one at a time
+            for offset in 0..length {
+                output.push(output[start + offset]);
+            }
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO: &[u8] = b"hello hello hello hello";
+    /// HELLO, with back-references under fixed codes
+    const FIXED: &[u8] = &[203, 72, 205, 201, 201, 87, 200, 64, 39, 1];
+    /// What DYNAMIC inflates to
+    fn abc() -> Vec<u8> {
+        let mut abc = vec![b'a'; 40];
+        abc.extend([b'b'; 20]);
+        abc.extend([b'c'; 10]);
+        abc
+    }
+
+    /// abc() under dynamic codes
+    const DYNAMIC: &[u8] = &[
+        5, 193, 1, 1, 0, 0, 0, 130, 160, 173, 216, 255, 15, 1, 0, 0, 0, 0, 64, 85, 85, 85, 85, 213,
+        182, 109, 219, 118,
+    ];
+    /// "stored data" in an uncompressed block
+    const UNCOMPRESSED: &[u8] = &[
+        1, 11, 0, 244, 255, 115, 116, 111, 114, 101, 100, 32, 100, 97, 116, 97,
+    ];
+
+    struct Entry<'a> {
+        name: &'a str,
+        method: u16,
+        flags: u16,
+        data: &'a [u8],
+        size: u32,
+        crc: u32,
+    }
+
+    fn zip(entries: &[Entry]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut directory = Vec::new();
+        for entry in entries {
+            let offset = output.len() as u32;
+            output.extend(LOCAL_HEADER.to_le_bytes());
+            output.extend(20u16.to_le_bytes());
+            output.extend(entry.flags.to_le_bytes());
+            output.extend(entry.method.to_le_bytes());
+            output.extend([0; 4]);
+            output.extend(entry.crc.to_le_bytes());
+            output.extend((entry.data.len() as u32).to_le_bytes());
+            output.extend(entry.size.to_le_bytes());
+            output.extend((entry.name.len() as u16).to_le_bytes());
+            output.extend(0u16.to_le_bytes());
+            output.extend(entry.name.as_bytes());
+            output.extend(entry.data);
+            directory.extend(DIRECTORY_ENTRY.to_le_bytes());
+            directory.extend([20, 0, 20, 0]);
+            directory.extend(entry.flags.to_le_bytes());
+            directory.extend(entry.method.to_le_bytes());
+            directory.extend([0; 4]);
+            directory.extend(entry.crc.to_le_bytes());
+            directory.extend((entry.data.len() as u32).to_le_bytes());
+            directory.extend(entry.size.to_le_bytes());
+            directory.extend((entry.name.len() as u16).to_le_bytes());
+            directory.extend([0; 12]);
+            directory.extend(offset.to_le_bytes());
+            directory.extend(entry.name.as_bytes());
+        }
+        let directory_offset = output.len() as u32;
+        output.extend(&directory);
+        output.extend(END_OF_DIRECTORY.to_le_bytes());
+        output.extend([0; 4]);
+        output.extend((entries.len() as u16).to_le_bytes());
+        output.extend((entries.len() as u16).to_le_bytes());
+        output.extend((directory.len() as u32).to_le_bytes());
+        output.extend(directory_offset.to_le_bytes());
+        output.extend([0; 2]);
+        output
+    }
+
+    /// A member holding these contents, stored as this data
+    fn entry<'a>(name: &'a str, method: u16, data: &'a [u8], contents: &[u8]) -> Entry<'a> {
+        Entry {
+            name,
+            method,
+            flags: 0,
+            data,
+            size: contents.len() as u32,
+            crc: crc32(0, contents),
+        }
+    }
+
+    /// Writes an archive to a temporary file, returning its path
+    fn write(test: &str, data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "radio-music-box-{}-{}.zip",
+            std::process::id(),
+            test
+        ));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn read(archive: &Path, name: &str) -> Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        open(&members_root(archive).join(name))?.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    #[test]
+    fn reads_members() {
+        let archive = write(
+            "members",
+            &zip(&[
+                entry("plain.txt", STORED, b"plain", b"plain"),
+                entry("fixed.txt", DEFLATED, FIXED, HELLO),
+                entry("dynamic.txt", DEFLATED, DYNAMIC, &abc()),
+                entry("uncompressed.txt", DEFLATED, UNCOMPRESSED, b"stored data"),
+            ]),
+        );
+        let members = list(&archive).unwrap();
+        assert_eq!(
+            members
+                .iter()
+                .map(|member| (member.name.as_str(), member.size()))
+                .collect::<Vec<_>>(),
+            [
+                ("plain.txt", 5),
+                ("fixed.txt", 23),
+                ("dynamic.txt", 70),
+                ("uncompressed.txt", 11)
+            ]
+        );
+        assert_eq!(read(&archive, "plain.txt").unwrap(), b"plain");
+        assert_eq!(read(&archive, "fixed.txt").unwrap(), HELLO);
+        assert_eq!(read(&archive, "dynamic.txt").unwrap(), abc());
+        assert_eq!(read(&archive, "uncompressed.txt").unwrap(), b"stored data");
+        assert_eq!(
+            read(&archive, "missing.txt").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+        std::fs::remove_file(archive).unwrap();
+    }
+
+    #[test]
+    fn seeks_within_deflated_member() {
+        let archive = write("seek", &zip(&[entry("a", DEFLATED, FIXED, HELLO)]));
+        let mut source = open(&members_root(&archive).join("a")).unwrap();
+        assert_eq!(source.byte_len(), Some(23));
+        source.seek(SeekFrom::End(-5)).unwrap();
+        let mut tail = String::new();
+        source.read_to_string(&mut tail).unwrap();
+        assert_eq!(tail, "hello");
+        std::fs::remove_file(archive).unwrap();
+    }
+
+    #[test]
+    fn inflates_only_what_is_read() {
+        let mut inflater = Inflater::new(DYNAMIC.to_vec(), 70, crc32(0, &abc()));
+        inflater.fill(10).unwrap();
+        assert!(inflater.output.len() < 70);
+        inflater.fill(70).unwrap();
+        assert_eq!(inflater.output.len(), 70);
+    }
+
+    #[test]
+    fn rejects_members_bigger_than_claimed() {
+        let archive = write(
+            "bomb",
+            &zip(&[Entry {
+                size: 10,
+                ..entry("a", DEFLATED, FIXED, HELLO)
+            }]),
+        );
+        assert_eq!(
+            read(&archive, "a").unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        std::fs::remove_file(archive).unwrap();
+    }
+
+    #[test]
+    fn rejects_members_smaller_than_claimed() {
+        let archive = write(
+            "short",
+            &zip(&[Entry {
+                size: 30,
+                ..entry("a", DEFLATED, FIXED, HELLO)
+            }]),
+        );
+        assert_eq!(
+            read(&archive, "a").unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        std::fs::remove_file(archive).unwrap();
+    }
+
+    #[test]
+    fn rejects_oversized_members() {
+        let archive = write(
+            "oversized",
+            &zip(&[
+                Entry {
+                    size: u32::MAX,
+                    ..entry("huge", DEFLATED, FIXED, HELLO)
+                },
+                Entry {
+                    size: 1 << 20,
+                    ..entry("past-end", STORED, b"tiny", b"tiny")
+                },
+            ]),
+        );
+        assert!(read(&archive, "huge").is_err());
+        assert!(read(&archive, "past-end").is_err());
+        std::fs::remove_file(archive).unwrap();
+    }
+
+    #[test]
+    fn rejects_encrypted_members() {
+        let archive = write(
+            "encrypted",
+            &zip(&[Entry {
+                flags: ENCRYPTED,
+                ..entry("secret", STORED, b"secret", b"secret")
+            }]),
+        );
+        assert_eq!(
+            read(&archive, "secret").unwrap_err().kind(),
+            ErrorKind::Unsupported
+        );
+        std::fs::remove_file(archive).unwrap();
+    }
+
+    #[test]
+    fn rejects_directory_past_end() {
+        let mut data = zip(&[entry("a", STORED, b"a", b"a")]);
+        let end = data.len() - 22;
+        data[end + 12..end + 16].copy_from_slice(&u32::MAX.to_le_bytes()[..]);
+        let archive = write("directory", &data);
+        assert!(list(&archive).is_err());
+        std::fs::remove_file(archive).unwrap();
+    }
+
+    #[test]
+    fn rejects_corrupt_data() {
+        let archive = write(
+            "corrupt",
+            &zip(&[
+                entry("truncated", DEFLATED, &FIXED[..4], HELLO),
+                entry("bad-block", DEFLATED, &[0xff, 0xff], HELLO),
+            ]),
+        );
+        assert!(read(&archive, "truncated").is_err());
+        assert!(read(&archive, "bad-block").is_err());
+        std::fs::remove_file(archive).unwrap();
+    }
+
+    #[test]
+    fn rejects_members_that_fail_their_crc() {
+        let archive = write(
+            "crc",
+            &zip(&[
+                entry("stored", STORED, b"plain", b"plaid"),
+                entry("deflated", DEFLATED, FIXED, b"hello hello hello jello"),
+            ]),
+        );
+        assert_eq!(
+            read(&archive, "stored").unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            read(&archive, "deflated").unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        // Reading part of a member again after it failed still fails
+        let mut source = open(&members_root(&archive).join("deflated")).unwrap();
+        let mut buffer = [0; 23];
+        assert!(source.read_exact(&mut buffer).is_err());
+        source.seek(SeekFrom::Start(0)).unwrap();
+        assert!(source.read(&mut buffer[..5]).is_err());
+        std::fs::remove_file(archive).unwrap();
+    }
+
+    #[test]
+    fn crc32_matches_zip() {
+        assert_eq!(crc32(0, b""), 0);
+        assert_eq!(crc32(0, b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(crc32(0, b"12345"), b"6789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn splits_member_paths() {
+        assert_eq!(
+            split(Path::new("/music/album.zip!/disc 1/01.flac")),
+            Some((
+                PathBuf::from("/music/album.zip"),
+                "disc 1/01.flac".to_string()
+            ))
+        );
+        assert_eq!(split(Path::new("/music/wow!/01.flac")), None);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::decoder::DecodedStream;
+use crate::encoder::EncodedStream;
+use crate::history::History;
+use crate::playlist::Playlist;
+use crate::quarantine::Quarantine;
+use crate::resume::Player;
+use crate::skippable_stream::{Skip, SkippableStream};
+use futures::{Stream, StreamExt};
+use hyper::body::Bytes;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+use symphonia::core::audio::Signal;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Notify, broadcast};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// How many frames a slow listener can fall behind before it skips ahead (about 10 seconds)
+const BACKLOG: usize = 400;
+/// How far ahead of real time the encoder may run
+const LEAD: Duration = Duration::from_millis(500);
+
+const MPEG1_BITRATES: [u32; 15] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const MPEG2_BITRATES: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+const SAMPLE_RATES: [u32; 3] = [44_100, 48_000, 32_000];
+
+/// A single stream of MP3 frames shared by every listener
+#[derive(Clone)]
+pub struct Broadcaster {
+    frames: broadcast::Sender<Bytes>,
+    listener_joined: Arc<Notify>,
+    skip: Skip,
+}
+
+impl Broadcaster {
+    /// The frames to send to a new listener, starting at the next frame boundary
+    pub fn listen(&self) -> impl Stream<Item = Bytes> + Send + Unpin + 'static {
+        let receiver = self.frames.subscribe();
+        self.listener_joined.notify_one();
+        Box::pin(futures::stream::unfold(
+            receiver,
+            |mut receiver| async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(frame) => return Some((frame, receiver)),
+                        // Every message is a whole frame, so skipping some keeps the stream valid
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            },
+        ))
+    }
+    /// Ends the current song for everyone
+    pub fn skip(&self) {
+        self.skip.skip();
+    }
+}
+
+pub fn start(
+    playlist: Playlist,
+    quarantine: Quarantine,
+    history: History,
+    player: Player,
+    exit: &broadcast::Sender<()>,
+) -> Result<(Broadcaster, JoinHandle<()>), ()> {
+    let (frames, _) = broadcast::channel(BACKLOG);
+    let songs = {
+        let frames = frames.clone();
+        playlist
+            .resume(player.clone())
+            .fuse()
+            .inspect(move |track| history.record(track, "broadcast", Some(frames.receiver_count())))
+    };
+    let (songs, skip) = SkippableStream::new(songs, move |track| {
+        DecodedStream::new(track, Some(quarantine.clone()))
+    });
+    let mut encoded = EncodedStream::new(
+        songs.inspect(move |buffer| player.played(buffer.frames(), buffer.spec().rate)),
+    )?;
+    let broadcaster = Broadcaster {
+        frames,
+        listener_joined: Default::default(),
+        skip,
+    };
+    let mut exit_rx = exit.subscribe();
+    let handle = {
+        let Broadcaster {
+            frames,
+            listener_joined,
+            ..
+        } = broadcaster.clone();
+        tokio::spawn(async move {
+            let mut buffer = Vec::new();
+            let mut start = Instant::now();
+            let mut sent = Duration::ZERO;
+            loop {
+                // Nobody is listening, so stop playing until someone is
+                if frames.receiver_count() == 0 {
+                    tokio::select! {
+                        _ = exit_rx.recv() => break,
+                        _ = listener_joined.notified() => {}
+                    }
+                    start = Instant::now();
+                    sent = Duration::ZERO;
+                    continue;
+                }
+                let data = tokio::select! {
+                    _ = exit_rx.recv() => break,
+                    data = encoded.next() => data,
+                };
+                let Some(data) = data else {
+                    eprintln!("Broadcast has nothing left to play");
+                    break;
+                };
+                buffer.extend_from_slice(&data);
+                let (complete, used) = whole_frames(&buffer);
+                for (frame, duration) in complete {
+                    if let Some(wait) = sent.checked_sub(start.elapsed() + LEAD) {
+                        tokio::time::sleep(wait).await;
+                    }
+                    let _ = frames.send(Bytes::copy_from_slice(&buffer[frame]));
+                    sent += duration;
+                }
+                buffer.drain(..used);
+            }
+        })
+    };
+    Ok((broadcaster, handle))
+}
+
+/// The whole frames in the buffer and how long each plays, and how much of the buffer they and the junk between
+/// them take up; the rest is the start of a frame still to come
+fn whole_frames(buffer: &[u8]) -> (Vec<(Range<usize>, Duration)>, usize) {
+    let mut complete = Vec::new();
+    let mut offset = 0;
+    while let Some((skipped, length, duration)) = next_frame(&buffer[offset..]) {
+        let frame = offset + skipped;
+        if frame + length > buffer.len() {
+            break;
+        }
+        complete.push((frame..frame + length, duration));
+        offset = frame + length;
+    }
+    (complete, offset)
+}
+
+/// Finds the next frame header and returns how much junk comes before it, the frame's length and how long it plays
+fn next_frame(data: &[u8]) -> Option<(usize, usize, Duration)> {
+    let mut skipped = 0;
+    while data.len() >= skipped + 4 {
+        if let Some((length, duration)) = frame_header(&data[skipped..skipped + 4]) {
+            return Some((skipped, length, duration));
+        }
+        skipped += 1;
+    }
+    None
+}
+
+/// Reads an MPEG layer III frame header
+fn frame_header(header: &[u8]) -> Option<(usize, Duration)> {
+    if header[0] != 0xFF || header[1] & 0xE0 != 0xE0 || (header[1] >> 1) & 3 != 1 {
+        return None;
+    }
+    let (bitrates, divisor, samples) = match (header[1] >> 3) & 3 {
+        3 => (&MPEG1_BITRATES, 1, 1152),
+        2 => (&MPEG2_BITRATES, 2, 576),
+        0 => (&MPEG2_BITRATES, 4, 576),
+        _ => return None,
+    };
+    let bitrate = *bitrates.get((header[2] >> 4) as usize)? * 1000;
+    let sample_rate = SAMPLE_RATES.get(((header[2] >> 2) & 3) as usize)? / divisor;
+    if bitrate == 0 {
+        return None;
+    }
+    let padding = ((header[2] >> 1) & 1) as usize;
+    let length = (samples / 8 * bitrate / sample_rate) as usize + padding;
+    Some((
+        length,
+        Duration::from_secs_f64(samples as f64 / sample_rate as f64),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame with the given header, filled out to its length
+    fn frame(header: [u8; 4]) -> Vec<u8> {
+        let (length, _) = frame_header(&header).expect("Not a frame header");
+        let mut frame = header.to_vec();
+        frame.resize(length, 0x55);
+        frame
+    }
+
+    #[test]
+    fn mpeg1_header() {
+        // 128 kbit/s at 44.1 kHz
+        let (length, duration) = frame_header(&[0xFF, 0xFB, 0x90, 0x00]).unwrap();
+        assert_eq!(length, 417);
+        assert_eq!(duration, Duration::from_secs_f64(1152.0 / 44_100.0));
+    }
+
+    #[test]
+    fn mpeg2_header() {
+        // 64 kbit/s at 22.05 kHz
+        let (length, duration) = frame_header(&[0xFF, 0xF3, 0x80, 0x00]).unwrap();
+        assert_eq!(length, 208);
+        assert_eq!(duration, Duration::from_secs_f64(576.0 / 22_050.0));
+        // MPEG 2.5, 32 kbit/s at 8 kHz
+        let (length, duration) = frame_header(&[0xFF, 0xE3, 0x48, 0x00]).unwrap();
+        assert_eq!(length, 288);
+        assert_eq!(duration, Duration::from_secs_f64(576.0 / 8_000.0));
+    }
+
+    #[test]
+    fn padding() {
+        let (length, _) = frame_header(&[0xFF, 0xFB, 0x92, 0x00]).unwrap();
+        assert_eq!(length, 418);
+    }
+
+    #[test]
+    fn invalid_headers() {
+        // Layer II, free format, a bad bit rate, a reserved version and a reserved sample rate
+        for header in [
+            [0xFF, 0xFD, 0x90, 0x00],
+            [0xFF, 0xFB, 0x00, 0x00],
+            [0xFF, 0xFB, 0xF0, 0x00],
+            [0xFF, 0xEB, 0x90, 0x00],
+            [0xFF, 0xFB, 0x9C, 0x00],
+            [0xFE, 0xFB, 0x90, 0x00],
+        ] {
+            assert!(frame_header(&header).is_none(), "{:02X?}", header);
+        }
+    }
+
+    #[test]
+    fn skips_junk() {
+        let mut data = vec![0x00, 0xFF, 0x12, 0x34];
+        data.extend(frame([0xFF, 0xFB, 0x90, 0x00]));
+        let (skipped, length, _) = next_frame(&data).unwrap();
+        assert_eq!((skipped, length), (4, 417));
+        assert!(next_frame(&[0x00, 0xFF, 0xFB]).is_none());
+    }
+
+    #[test]
+    fn frames_split_across_buffers() {
+        let mut data = frame([0xFF, 0xFB, 0x90, 0x00]);
+        data.extend([0x00, 0x00]);
+        data.extend(frame([0xFF, 0xFB, 0x92, 0x00]));
+        data.extend(frame([0xFF, 0xF3, 0x80, 0x00]));
+        for split in [1, 3, 100, 417, 418, 419, 420, 700, data.len() - 1] {
+            let mut buffer = data[..split].to_vec();
+            let (mut found, used) = whole_frames(&buffer);
+            buffer.drain(..used);
+            let consumed = used;
+            buffer.extend_from_slice(&data[split..]);
+            let (rest, used) = whole_frames(&buffer);
+            assert_eq!(used, buffer.len(), "split at {}", split);
+            found.extend(
+                rest.into_iter().map(|(frame, duration)| {
+                    (frame.start + consumed..frame.end + consumed, duration)
+                }),
+            );
+            let frames: Vec<_> = found.into_iter().map(|(frame, _)| frame).collect();
+            assert_eq!(frames, [0..417, 419..837, 837..1045], "split at {}", split);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use crate::library::{Library, Tracks, Unplayable};
+use crate::state;
+use crate::track::Track;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+const VERSION: u32 = 7;
+
+#[derive(Deserialize, Serialize)]
+struct CacheFile {
+    version: u32,
+    tracks: Vec<Arc<Track>>,
+    #[serde(default)]
+    unplayable: Vec<UnplayableFile>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct UnplayableFile {
+    path: Arc<Path>,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+pub fn load(path: &Path) -> (Tracks, Unplayable) {
+    match state::read::<CacheFile>(path) {
+        Some(cache) if cache.version == VERSION => (
+            cache
+                .tracks
+                .into_iter()
+                .map(|track| (track.path.clone(), track))
+                .collect(),
+            cache
+                .unplayable
+                .into_iter()
+                .map(|file| (file.path, (file.size, file.modified)))
+                .collect(),
+        ),
+        Some(_) => {
+            eprintln!("Library cache {} is outdated; ignoring", path.display());
+            Default::default()
+        }
+        None => Default::default(),
+    }
+}
+
+pub fn save(path: &Path, library: &Library) {
+    state::write(
+        path,
+        &CacheFile {
+            version: VERSION,
+            tracks: library.tracks().cloned().collect(),
+            unplayable: library
+                .roots
+                .iter()
+                .flat_map(|root| &root.unplayable)
+                .map(|(path, &(size, modified))| UnplayableFile {
+                    path: path.clone(),
+                    size,
+                    modified,
+                })
+                .collect(),
+        },
+    );
+}
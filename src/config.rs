@@ -0,0 +1,160 @@
+use crate::filter::Filter;
+use crate::playlist::{Mode, Source};
+use crate::queries::Queries;
+use crate::schedule::{Schedule, ScheduleEntry, TimeOfDay, parse_day};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Default)]
+pub struct Config {
+    pub stations: BTreeMap<String, Arc<Station>>,
+    /// What the local player and broadcast play at different times
+    pub schedule: Option<Arc<Schedule>>,
+}
+
+pub struct Station {
+    pub name: String,
+    pub filter: Option<Arc<Filter>>,
+    /// The name of a saved query to play instead of a filter
+    pub query: Option<String>,
+    pub mode: Mode,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    stations: BTreeMap<String, StationConfig>,
+    schedule: Option<Vec<ScheduleConfig>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StationConfig {
+    filter: Option<String>,
+    query: Option<String>,
+    mode: Option<String>,
+    gap: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScheduleConfig {
+    days: Option<Vec<String>>,
+    from: String,
+    to: String,
+    station: Option<String>,
+    filter: Option<String>,
+    mode: Option<String>,
+    gap: Option<usize>,
+}
+
+pub fn load(path: &Path, queries: &Queries, spread_gap: usize) -> Result<Config, String> {
+    let contents =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let file: ConfigFile = serde_json::from_slice(&contents)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut stations = BTreeMap::new();
+    for (name, station) in file.stations {
+        if station.filter.is_some() && station.query.is_some() {
+            return Err(format!(
+                "Station {}: only one of filter and query may be given",
+                name
+            ));
+        }
+        let filter = match station.filter {
+            Some(filter) => Some(Arc::new(
+                filter
+                    .parse()
+                    .map_err(|e| format!("Station {}: {}", name, e))?,
+            )),
+            None => None,
+        };
+        let mode = parse_mode(station.mode, station.gap, spread_gap)
+            .map_err(|e| format!("Station {}: {}", name, e))?;
+        stations.insert(
+            name.clone(),
+            Arc::new(Station {
+                name,
+                filter,
+                query: station.query,
+                mode,
+            }),
+        );
+    }
+    let schedule = match file.schedule {
+        Some(entries) => Some(Arc::new(Schedule {
+            entries: entries
+                .into_iter()
+                .enumerate()
+                .map(|(index, entry)| {
+                    parse_schedule_entry(entry, &stations, queries, spread_gap)
+                        .map_err(|e| format!("Schedule entry {}: {}", index + 1, e))
+                })
+                .collect::<Result<_, _>>()?,
+        })),
+        None => None,
+    };
+    Ok(Config { stations, schedule })
+}
+
+fn parse_mode(mode: Option<String>, gap: Option<usize>, spread_gap: usize) -> Result<Mode, String> {
+    let mode = match mode {
+        Some(mode) => mode.parse()?,
+        None => Mode::Shuffle,
+    };
+    Ok(match (mode, gap) {
+        (Mode::Spread(_), Some(gap)) => Mode::Spread(gap),
+        (Mode::Spread(_), None) => Mode::Spread(spread_gap),
+        (mode, _) => mode,
+    })
+}
+
+fn parse_schedule_entry(
+    entry: ScheduleConfig,
+    stations: &BTreeMap<String, Arc<Station>>,
+    queries: &Queries,
+    spread_gap: usize,
+) -> Result<ScheduleEntry, String> {
+    let days = match entry.days {
+        Some(names) => {
+            let mut days = [false; 7];
+            for name in names {
+                days[parse_day(&name)?] = true;
+            }
+            days
+        }
+        None => [true; 7],
+    };
+    let TimeOfDay(start) = entry.from.parse()?;
+    let TimeOfDay(end) = entry.to.parse()?;
+    let (source, default_mode) = match (entry.station, entry.filter) {
+        (Some(name), None) => match stations.get(&name) {
+            Some(station) => (
+                match (&station.filter, &station.query) {
+                    (Some(filter), _) => Source::Filter(filter.clone()),
+                    (None, Some(query)) => Source::Query(queries.clone(), query.clone()),
+                    (None, None) => Source::Library,
+                },
+                station.mode,
+            ),
+            // Any other station is a playlist file, which the scan has yet to find
+            None => (Source::PlaylistFile(name), Mode::Ordered),
+        },
+        (None, Some(filter)) => (Source::Filter(Arc::new(filter.parse()?)), Mode::Shuffle),
+        _ => return Err("exactly one of station and filter must be given".to_string()),
+    };
+    let mode = match (entry.mode, entry.gap) {
+        (None, None) => default_mode,
+        (mode, gap) => parse_mode(mode, gap, spread_gap)?,
+    };
+    Ok(ScheduleEntry {
+        days,
+        start,
+        end,
+        source,
+        mode,
+    })
+}
@@ -0,0 +1,239 @@
+use crate::playlist_file::normalize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub files: Vec<CueFile>,
+}
+
+pub struct CueFile {
+    pub path: PathBuf,
+    pub tracks: Vec<CueTrack>,
+}
+
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub start: Duration,
+}
+
+impl CueSheet {
+    pub fn is_cue_sheet(path: &Path) -> bool {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("cue"))
+    }
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        let contents = std::fs::read(path)?;
+        let contents = String::from_utf8_lossy(&contents);
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let mut sheet = CueSheet {
+            title: None,
+            performer: None,
+            files: Vec::new(),
+        };
+        // Tracks are only kept once an INDEX 01 gives them a position
+        let mut pending: Option<(u32, Option<String>, Option<String>)> = None;
+        for line in contents.lines() {
+            let arguments = split_arguments(line.trim_start_matches('\u{feff}'));
+            let Some((command, arguments)) = arguments.split_first() else {
+                continue;
+            };
+            match (command.to_ascii_uppercase().as_str(), arguments) {
+                ("FILE", [name, ..]) => {
+                    pending = None;
+                    sheet.files.push(CueFile {
+                        path: normalize(&directory.join(name.replace('\\', "/"))),
+                        tracks: Vec::new(),
+                    });
+                }
+                ("TRACK", [number, ..]) => {
+                    pending = number.parse().ok().map(|number| (number, None, None));
+                }
+                ("TITLE", [title, ..]) => match &mut pending {
+                    Some((_, pending_title, _)) => *pending_title = Some(title.clone()),
+                    None => sheet.title = Some(title.clone()),
+                },
+                ("PERFORMER", [performer, ..]) => match &mut pending {
+                    Some((_, _, pending_performer)) => *pending_performer = Some(performer.clone()),
+                    None => sheet.performer = Some(performer.clone()),
+                },
+                ("INDEX", [index, time, ..]) if index.parse() == Ok(1) => {
+                    if let (Some(file), Some((number, title, performer)), Some(start)) =
+                        (sheet.files.last_mut(), pending.take(), parse_time(time))
+                    {
+                        file.tracks.push(CueTrack {
+                            number,
+                            title,
+                            performer,
+                            start,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        sheet.files.retain(|file| !file.tracks.is_empty());
+        Ok(sheet)
+    }
+}
+
+fn split_arguments(line: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            arguments.push(chars.by_ref().take_while(|&c| c != '"').collect());
+        } else {
+            let mut argument = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                argument.push(c);
+            }
+            arguments.push(argument);
+        }
+    }
+    arguments
+}
+
+fn parse_time(time: &str) -> Option<Duration> {
+    // MM:SS:FF where there are 75 frames per second
+    let mut parts = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (Some(Some(minutes)), Some(Some(seconds)), Some(Some(frames)), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    Some(Duration::from_secs(minutes * 60 + seconds) + Duration::from_millis(frames * 1000 / 75))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sheet_directory(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("radio-music-box-{}-{}", std::process::id(), test))
+    }
+
+    /// Writes a sheet to a file of its own and reads it back
+    fn read(test: &str, contents: &str) -> CueSheet {
+        let directory = sheet_directory(test);
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("album.cue");
+        std::fs::write(&path, contents).unwrap();
+        let sheet = CueSheet::read(&path).unwrap();
+        std::fs::remove_dir_all(directory).unwrap();
+        sheet
+    }
+
+    #[test]
+    fn reads_tracks_and_their_details() {
+        let sheet = read(
+            "cue-details",
+            "\u{feff}REM GENRE Jazz\r\n\
+             PERFORMER \"Miles Davis\"\r\n\
+             TITLE \"Kind of Blue\"\r\n\
+             FILE \"Kind of Blue.flac\" WAVE\r\n\
+             \x20 TRACK 01 AUDIO\r\n\
+             \x20   TITLE \"So What\"\r\n\
+             \x20   INDEX 00 00:00:00\r\n\
+             \x20   INDEX 01 00:00:32\r\n\
+             \x20 TRACK 02 AUDIO\r\n\
+             \x20   TITLE \"Freddie Freeloader\"\r\n\
+             \x20   PERFORMER \"Miles Davis Sextet\"\r\n\
+             \x20   INDEX 01 09:22:00\r\n",
+        );
+        assert_eq!(sheet.title.as_deref(), Some("Kind of Blue"));
+        assert_eq!(sheet.performer.as_deref(), Some("Miles Davis"));
+        assert_eq!(sheet.files.len(), 1);
+        let tracks = &sheet.files[0].tracks;
+        assert_eq!(
+            tracks
+                .iter()
+                .map(|track| (
+                    track.number,
+                    track.title.as_deref(),
+                    track.performer.as_deref()
+                ))
+                .collect::<Vec<_>>(),
+            [
+                (1, Some("So What"), None),
+                (2, Some("Freddie Freeloader"), Some("Miles Davis Sextet"))
+            ]
+        );
+        assert_eq!(tracks[0].start, Duration::from_millis(32 * 1000 / 75));
+        assert_eq!(tracks[1].start, Duration::from_secs(9 * 60 + 22));
+    }
+
+    #[test]
+    fn resolves_files_from_the_sheet() {
+        let directory = sheet_directory("cue-paths");
+        let sheet = read(
+            "cue-paths",
+            "FILE \"disc 1.wav\" WAVE\nTRACK 01 AUDIO\nINDEX 01 00:00:00\n\
+             FILE Disc2\\side.wav WAVE\nTRACK 02 AUDIO\nINDEX 01 00:00:00\n\
+             FILE \"../elsewhere/./bonus.wav\" WAVE\nTRACK 03 AUDIO\nINDEX 01 00:00:00\n\
+             FILE \"/music/absolute.wav\" WAVE\nTRACK 04 AUDIO\nINDEX 01 00:00:00\n",
+        );
+        assert_eq!(
+            sheet
+                .files
+                .iter()
+                .map(|file| file.path.clone())
+                .collect::<Vec<_>>(),
+            [
+                directory.join("disc 1.wav"),
+                directory.join("Disc2/side.wav"),
+                std::env::temp_dir().join("elsewhere/bonus.wav"),
+                PathBuf::from("/music/absolute.wav"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_only_tracks_with_a_start() {
+        let sheet = read(
+            "cue-starts",
+            "TRACK 01 AUDIO\nINDEX 01 00:00:00\n\
+             FILE empty.wav WAVE\n\
+             FILE album.wav WAVE\n\
+             TRACK 01 AUDIO\nINDEX 00 00:00:00\n\
+             TRACK 02 AUDIO\nINDEX 01 01:02:03\n\
+             TRACK 03 AUDIO\nINDEX 01 1:02\n\
+             TRACK 04 AUDIO\nINDEX 01 01:02:03:04\n\
+             TRACK five AUDIO\nINDEX 01 02:00:00\n\
+             TRACK 06 AUDIO\nINDEX 01 03:00:74\n",
+        );
+        assert_eq!(sheet.files.len(), 1);
+        assert!(sheet.files[0].path.ends_with("album.wav"));
+        assert_eq!(
+            sheet.files[0]
+                .tracks
+                .iter()
+                .map(|track| (track.number, track.start))
+                .collect::<Vec<_>>(),
+            [
+                (2, Duration::from_secs(62) + Duration::from_millis(40)),
+                (6, Duration::from_secs(180) + Duration::from_millis(986)),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_quoted_arguments() {
+        assert_eq!(
+            split_arguments("  TITLE \"Round  Midnight\" extra"),
+            ["TITLE", "Round  Midnight", "extra"]
+        );
+        assert_eq!(
+            split_arguments("FILE \"unterminated"),
+            ["FILE", "unterminated"]
+        );
+        assert!(split_arguments("   ").is_empty());
+    }
+}
@@ -1,7 +1,10 @@
+use crate::archive;
+use crate::quarantine::Quarantine;
+use crate::track::{Span, Track};
 use futures::Stream;
 use rubato::{FftFixedIn, Resampler};
 use std::collections::VecDeque;
-use std::fs::File;
+use std::fmt::Display;
 use std::mem::swap;
 use std::path::Path;
 use std::pin::Pin;
@@ -11,7 +14,7 @@ use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Channels, Signal, Sign
 use symphonia::core::codecs::Decoder;
 use symphonia::core::conv::IntoSample;
 use symphonia::core::errors::Error;
-use symphonia::core::formats::Track;
+use symphonia::core::formats::{SeekMode, SeekTo, Track as FormatTrack};
 use symphonia::core::io::MediaSourceStream;
 use symphonia::core::probe::ProbeResult;
 use symphonia::core::sample::Sample;
@@ -20,19 +23,32 @@ pub enum DecodedStream {
     Empty,
     Song {
         song: Arc<Path>,
-        tracks: VecDeque<Track>,
-        prober: ProbeResult,
+        /// Where to count failures; without one they are only logged
+        quarantine: Option<Quarantine>,
+        tracks: VecDeque<FormatTrack>,
+        prober: Box<ProbeResult>,
         current_track: Option<(Box<dyn Decoder>, u32, ResamplingCopy)>,
+        bounds: Option<Bounds>,
     },
 }
 
-impl From<Arc<Path>> for DecodedStream {
-    fn from(song: Arc<Path>) -> Self {
-        let Ok(file) = File::open(&song) else {
-            eprintln!("Can't open {}", song.display());
-            return DecodedStream::Empty;
+pub struct Bounds {
+    track_id: u32,
+    start: u64,
+    end: Option<u64>,
+}
+
+impl DecodedStream {
+    pub fn new(track: Arc<Track>, quarantine: Option<Quarantine>) -> Self {
+        let song = track.path.clone();
+        let file = match archive::open(track.file()) {
+            Ok(file) => file,
+            Err(e) => {
+                record_failure(&quarantine, &song, format_args!("Can't open: {}", e));
+                return DecodedStream::Empty;
+            }
         };
-        let source = MediaSourceStream::new(Box::new(file), Default::default());
+        let source = MediaSourceStream::new(file, Default::default());
         match symphonia::default::get_probe().format(
             &Default::default(),
             source,
@@ -40,35 +56,96 @@ impl From<Arc<Path>> for DecodedStream {
             &Default::default(),
         ) {
             Err(e) => {
-                eprintln!("Failed to read {}: {}", song.display(), e);
+                record_failure(&quarantine, &song, format_args!("Failed to read: {}", e));
                 DecodedStream::Empty
             }
-            Ok(prober) => {
-                let tracks: VecDeque<_> = prober.format.tracks().into_iter().cloned().collect();
+            Ok(mut prober) => {
+                let tracks: VecDeque<_> = prober.format.tracks().iter().cloned().collect();
                 if tracks.is_empty() {
-                    eprintln!("No tracks in {}; Skipping", song.display());
+                    record_failure(&quarantine, &song, "No tracks");
                     return DecodedStream::Empty;
                 }
+                let bounds = match &track.span {
+                    None => None,
+                    Some(span) => match Bounds::seek(&mut prober, span) {
+                        Ok(bounds) => Some(bounds),
+                        Err(e) => {
+                            record_failure(
+                                &quarantine,
+                                &song,
+                                format_args!("Failed to seek: {}", e),
+                            );
+                            return DecodedStream::Empty;
+                        }
+                    },
+                };
                 DecodedStream::Song {
                     song,
+                    quarantine,
                     tracks,
-                    prober,
+                    prober: Box::new(prober),
                     current_track: None,
+                    bounds,
                 }
             }
         }
     }
 }
 
+fn record_failure(quarantine: &Option<Quarantine>, song: &Arc<Path>, error: impl Display) {
+    match quarantine {
+        Some(quarantine) => quarantine.record_failure(song, error),
+        None => eprintln!("{}: {}", song.display(), error),
+    }
+}
+
+fn record_success(quarantine: &Option<Quarantine>, song: &Path) {
+    if let Some(quarantine) = quarantine {
+        quarantine.record_success(song);
+    }
+}
+
+impl Bounds {
+    fn seek(prober: &mut ProbeResult, span: &Span) -> Result<Self, Error> {
+        let seeked = prober.format.seek(
+            SeekMode::Accurate,
+            SeekTo::Time {
+                time: span.start.into(),
+                track_id: None,
+            },
+        )?;
+        let end = match span.end {
+            None => None,
+            Some(end) => Some(
+                prober
+                    .format
+                    .tracks()
+                    .iter()
+                    .find(|track| track.id == seeked.track_id)
+                    .and_then(|track| track.codec_params.time_base)
+                    .ok_or(Error::Unsupported("unknown time base"))?
+                    .calc_timestamp(end.into()),
+            ),
+        };
+        Ok(Bounds {
+            track_id: seeked.track_id,
+            start: seeked.required_ts,
+            end,
+        })
+    }
+}
+
 impl Stream for DecodedStream {
     type Item = AudioBuffer<i16>;
 
     fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let DecodedStream::Song {
             song,
+            quarantine,
             prober,
             tracks,
             current_track,
+            bounds,
         } = self.get_mut()
         else {
             return Poll::Ready(None);
@@ -77,13 +154,26 @@ impl Stream for DecodedStream {
             let packet = match prober.format.next_packet() {
                 Ok(packet) => packet,
                 Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    record_success(quarantine, song);
                     return Poll::Ready(None);
                 }
                 Err(e) => {
-                    eprintln!("Bad packet in {}: {}", song.display(), e);
+                    record_failure(quarantine, song, format_args!("Bad packet: {}", e));
                     return Poll::Ready(None);
                 }
             };
+            if let Some(bounds) = bounds
+                && packet.track_id() == bounds.track_id
+            {
+                if bounds.end.is_some_and(|end| packet.ts() >= end) {
+                    record_success(quarantine, song);
+                    return Poll::Ready(None);
+                }
+                // An accurate seek can land a little early, so drop the lead-in
+                if packet.ts() + packet.dur() <= bounds.start {
+                    continue;
+                }
+            }
             let mut current_track_temp = None;
             swap(&mut current_track_temp, current_track);
             let (mut decoder, track_id, mut resampler) = match current_track_temp
@@ -92,20 +182,21 @@ impl Stream for DecodedStream {
                 None => match tracks.pop_front() {
                     None => return Poll::Ready(None),
                     Some(track) => {
-                        let Ok(decoder) = symphonia::default::get_codecs()
+                        let decoder = match symphonia::default::get_codecs()
                             .make(&track.codec_params, &Default::default())
-                        else {
-                            eprintln!("Bad track in {}", song.display());
-                            return Poll::Ready(None);
-                        };
-                        match track
-                            .codec_params
-                            .sample_rate
-                            .map(|rate| ResamplingCopy::new(rate))
-                            .flatten()
                         {
+                            Ok(decoder) => decoder,
+                            Err(e) => {
+                                record_failure(quarantine, song, format_args!("Bad track: {}", e));
+                                return Poll::Ready(None);
+                            }
+                        };
+                        match track.codec_params.sample_rate.and_then(ResamplingCopy::new) {
                             Some(resampler) => (decoder, track.id, resampler),
-                            None => return Poll::Ready(None),
+                            None => {
+                                record_failure(quarantine, song, "Cannot resample track");
+                                return Poll::Ready(None);
+                            }
                         }
                     }
                 },
@@ -114,7 +205,7 @@ impl Stream for DecodedStream {
 
             let result = match decoder.decode(&packet) {
                 Err(e) => {
-                    eprintln!("Decode error in {}: {}", song.display(), e);
+                    record_failure(quarantine, song, format_args!("Decode error: {}", e));
                     Some(Poll::Ready(None))
                 }
                 Ok(data) => {
@@ -149,7 +240,7 @@ pub enum ResamplingCopy {
     Matched,
     Resample {
         inputs: Vec<Vec<f32>>,
-        resampler: FftFixedIn<f32>,
+        resampler: Box<FftFixedIn<f32>>,
     },
 }
 impl ResamplingCopy {
@@ -160,7 +251,7 @@ impl ResamplingCopy {
             match FftFixedIn::<f32>::new(rate as usize, 44100, 1024, 2, 2) {
                 Ok(resampler) => Some(ResamplingCopy::Resample {
                     inputs: vec![Vec::new(), Vec::new()],
-                    resampler,
+                    resampler: Box::new(resampler),
                 }),
                 Err(e) => {
                     eprintln!("Failed to construct resampler: {}", e);
@@ -194,8 +285,8 @@ impl ResamplingCopy {
                 Some(output)
             }
             ResamplingCopy::Resample { inputs, resampler } => {
-                for channel in 0..2 {
-                    inputs[channel].extend(
+                for (channel, input_channel) in inputs.iter_mut().enumerate() {
+                    input_channel.extend(
                         input
                             .chan(if input.spec().channels.contains(Channels::FRONT_RIGHT) {
                                 channel
@@ -207,7 +298,6 @@ impl ResamplingCopy {
                     );
                 }
                 let mut buffer: Vec<_> = (0..2)
-                    .into_iter()
                     .map(|_| vec![0f32; resampler.output_frames_next()])
                     .collect();
 
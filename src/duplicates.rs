@@ -0,0 +1,174 @@
+use crate::SongList;
+use crate::fingerprint::Fingerprint;
+use crate::quarantine::Quarantine;
+use crate::state;
+use crate::state::{ByPath, Persisted};
+use crate::track::Track;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::TryRecvError;
+use tokio::task::JoinHandle;
+
+const MAX_DURATION_DIFFERENCE: Duration = Duration::from_millis(1500);
+
+#[derive(Default)]
+pub struct Duplicates {
+    groups: Vec<Vec<Arc<Path>>>,
+    membership: HashMap<Arc<Path>, usize>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    path: Arc<Path>,
+    size: u64,
+    modified: Option<SystemTime>,
+    /// Missing if the track could not be fingerprinted, so it isn't retried until it changes
+    fingerprint: Option<Fingerprint>,
+}
+
+impl Duplicates {
+    pub fn groups(&self) -> &[Vec<Arc<Path>>] {
+        &self.groups
+    }
+    pub fn group(&self, path: &Path) -> Option<&[Arc<Path>]> {
+        self.membership
+            .get(path)
+            .map(|&index| self.groups[index].as_slice())
+    }
+    fn find(tracks: &[Arc<Track>], entries: &BTreeMap<Arc<Path>, Entry>, threshold: f32) -> Self {
+        let mut candidates: Vec<_> = tracks
+            .iter()
+            .filter_map(|track| {
+                Some((
+                    track.duration?,
+                    &track.path,
+                    entries.get(&track.path)?.fingerprint.as_ref()?,
+                ))
+            })
+            .collect();
+        candidates.sort_by_key(|(duration, _, _)| *duration);
+        let mut parents: Vec<usize> = (0..candidates.len()).collect();
+        fn root(parents: &mut [usize], mut index: usize) -> usize {
+            while parents[index] != index {
+                parents[index] = parents[parents[index]];
+                index = parents[index];
+            }
+            index
+        }
+        for (index, (duration, _, fingerprint)) in candidates.iter().enumerate() {
+            // Recordings of differing lengths aren't the same, so only compare near neighbours
+            for (other, (other_duration, _, other_fingerprint)) in
+                candidates.iter().enumerate().skip(index + 1)
+            {
+                if *other_duration - *duration > MAX_DURATION_DIFFERENCE {
+                    break;
+                }
+                if fingerprint.distance(other_fingerprint) <= threshold {
+                    let (a, b) = (root(&mut parents, index), root(&mut parents, other));
+                    parents[a] = b;
+                }
+            }
+        }
+        let mut grouped: BTreeMap<usize, Vec<Arc<Path>>> = BTreeMap::new();
+        for (index, (_, path, _)) in candidates.iter().enumerate() {
+            let group = root(&mut parents, index);
+            grouped.entry(group).or_default().push((*path).clone());
+        }
+        let groups: Vec<_> = grouped
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .collect();
+        let membership = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(index, group)| group.iter().map(move |path| (path.clone(), index)))
+            .collect();
+        Duplicates { groups, membership }
+    }
+}
+
+pub fn start(
+    songs: SongList,
+    quarantine: Quarantine,
+    path: Option<PathBuf>,
+    threshold: f32,
+    exit: &broadcast::Sender<()>,
+) -> JoinHandle<()> {
+    let mut exit_rx = exit.subscribe();
+    tokio::spawn(async move {
+        let entries = path
+            .as_deref()
+            .and_then(state::read::<Vec<Entry>>)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+        let entries = Persisted::new(path, ByPath(entries));
+        let mut regroup = true;
+        loop {
+            let tracks: Vec<Arc<Track>> = songs.read().await.tracks().cloned().collect();
+            let present: HashSet<&Path> = tracks.iter().map(|track| &*track.path).collect();
+            let mut changed = {
+                let mut entries = entries.lock();
+                let count = entries.len();
+                entries.retain(|path, _| present.contains(&**path));
+                entries.len() != count
+            };
+            if changed {
+                entries.changed();
+            }
+            for track in &tracks {
+                if !matches!(exit_rx.try_recv(), Err(TryRecvError::Empty)) {
+                    entries.save();
+                    return;
+                }
+                if quarantine.is_quarantined(&track.path)
+                    || entries.lock().get(&track.path).is_some_and(|entry| {
+                        entry.size == track.size && entry.modified == track.modified
+                    })
+                {
+                    continue;
+                }
+                let fingerprint = {
+                    let track = track.clone();
+                    tokio::task::spawn_blocking(move || Fingerprint::compute(track))
+                        .await
+                        .unwrap_or_default()
+                };
+                entries.lock().insert(
+                    track.path.clone(),
+                    Entry {
+                        path: track.path.clone(),
+                        size: track.size,
+                        modified: track.modified,
+                        fingerprint,
+                    },
+                );
+                entries.changed();
+                changed = true;
+            }
+            if changed || regroup {
+                let duplicates = Duplicates::find(&tracks, &entries.lock(), threshold);
+                if !duplicates.groups.is_empty() {
+                    eprintln!(
+                        "Found {} groups of duplicate recordings",
+                        duplicates.groups.len()
+                    );
+                }
+                let mut library = songs.write().await;
+                library.duplicates = duplicates;
+                library.generation += 1;
+                regroup = false;
+            }
+            tokio::select! {
+                _ = exit_rx.recv() => break,
+                _ = tokio::time::sleep(Duration::from_secs(60)) => {}
+            }
+        }
+        entries.save();
+    })
+}
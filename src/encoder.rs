@@ -21,7 +21,7 @@ impl<I> EncodedStream<I> {
             input,
             lame: unsafe {
                 let handle = lame_init();
-                if handle == null_mut() {
+                if handle.is_null() {
                     return Err(());
                 }
 
@@ -45,7 +45,7 @@ impl<I: Stream<Item = AudioBuffer<i16>> + Unpin> Stream for EncodedStream<I> {
         let encoder = self.get_mut();
         match encoder.input.poll_next_unpin(cx) {
             Poll::Ready(None) => {
-                if encoder.lame == null_mut() {
+                if encoder.lame.is_null() {
                     Poll::Ready(None)
                 } else {
                     let mut buffer = vec![0_u8; 7200];
@@ -90,7 +90,7 @@ impl<I: Stream<Item = AudioBuffer<i16>> + Unpin> Stream for EncodedStream<I> {
 
 impl<I> Drop for EncodedStream<I> {
     fn drop(&mut self) {
-        if self.lame != null_mut() {
+        if !self.lame.is_null() {
             unsafe {
                 lame_close(self.lame);
             }
@@ -0,0 +1,89 @@
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub const IGNORE_FILE: &str = ".radioignore";
+
+pub struct Exclusions {
+    root: PathBuf,
+    excludes: Gitignore,
+    ignore_files: Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>,
+}
+
+impl Exclusions {
+    pub fn new(root: PathBuf, excludes: &[String]) -> Result<Self, ignore::Error> {
+        let mut builder = GitignoreBuilder::new(&root);
+        for exclude in excludes {
+            builder.add_line(None, exclude)?;
+        }
+        Ok(Exclusions {
+            excludes: builder.build()?,
+            root,
+            ignore_files: Default::default(),
+        })
+    }
+    pub fn is_ignore_file(path: &Path) -> bool {
+        path.file_name() == Some(OsStr::new(IGNORE_FILE))
+    }
+    pub fn reload(&self) {
+        self.ignore_files
+            .lock()
+            .expect("Failed to unlock ignore files")
+            .clear();
+    }
+    pub fn is_inside(&self, path: &Path) -> bool {
+        path.starts_with(&self.root)
+    }
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        if Self::is_ignore_file(path) {
+            return true;
+        }
+        if path == self.root || !path.starts_with(&self.root) {
+            return false;
+        }
+        if self
+            .excludes
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+        {
+            return true;
+        }
+        // The closest ignore file with an opinion wins, as in git
+        for directory in path
+            .ancestors()
+            .skip(1)
+            .take_while(|directory| directory.starts_with(&self.root))
+        {
+            if let Some(ignore) = self.ignore_file(directory) {
+                match ignore.matched_path_or_any_parents(path, is_dir) {
+                    Match::None => continue,
+                    result => return result.is_ignore(),
+                }
+            }
+        }
+        false
+    }
+    fn ignore_file(&self, directory: &Path) -> Option<Arc<Gitignore>> {
+        let mut ignore_files = self
+            .ignore_files
+            .lock()
+            .expect("Failed to unlock ignore files");
+        ignore_files
+            .entry(directory.to_path_buf())
+            .or_insert_with(|| {
+                let path = directory.join(IGNORE_FILE);
+                if !path.is_file() {
+                    return None;
+                }
+                let (ignore, error) = Gitignore::new(&path);
+                if let Some(e) = error {
+                    eprintln!("Problem in {}: {}", path.display(), e);
+                }
+                Some(Arc::new(ignore))
+            })
+            .clone()
+    }
+}
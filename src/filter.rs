@@ -0,0 +1,407 @@
+use crate::track::Track;
+use globset::{GlobBuilder, GlobMatcher};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::{CharIndices, FromStr};
+
+/// A query over songs, such as `artist:"Daft Punk" OR (genre:jazz AND year<1970) NOT path:Live/**`
+///
+/// Terms are either a glob over the song's path within its music root (`Instrumental/**`), a glob over a metadata
+/// field (`genre:disco`), or a comparison of a numeric field (`year<1970`). Text matching is case-insensitive. Terms
+/// are combined with `AND` (which may be left out), `OR`, parentheses and `NOT`. `NOT` before a term negates it; `NOT`
+/// between terms excludes what follows from what came before.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    text: String,
+    expression: Expression,
+}
+
+#[derive(Clone, Debug)]
+enum Expression {
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Text(TextField, GlobMatcher),
+    Number(NumberField, Ordering, bool, u64),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum TextField {
+    Path,
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Codec,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum NumberField {
+    Year,
+    Track,
+    Disc,
+    Duration,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Term(String),
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut tokens = tokens.into_iter().peekable();
+        let expression = parse_expression(&mut tokens)?;
+        if let Some(token) = tokens.next() {
+            return Err(format!("Unexpected {:?} in {}", token, s));
+        }
+        Ok(Filter {
+            text: s.to_string(),
+            expression,
+        })
+    }
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, root: &Path, track: &Track) -> bool {
+        self.expression.matches(root, track)
+    }
+}
+
+impl Expression {
+    fn matches(&self, root: &Path, track: &Track) -> bool {
+        match self {
+            Expression::And(left, right) => left.matches(root, track) && right.matches(root, track),
+            Expression::Or(left, right) => left.matches(root, track) || right.matches(root, track),
+            Expression::Not(inner) => !inner.matches(root, track),
+            Expression::Text(field, pattern) => match field {
+                TextField::Path => Some(track.path.strip_prefix(root).unwrap_or(&track.path)),
+                TextField::Title => track.title.as_deref().map(Path::new),
+                TextField::Artist => track.artist.as_deref().map(Path::new),
+                TextField::Album => track.album.as_deref().map(Path::new),
+                TextField::Genre => track.genre.as_deref().map(Path::new),
+                TextField::Codec => track.codec.as_deref().map(Path::new),
+            }
+            .is_some_and(|value| pattern.is_match(value)),
+            Expression::Number(field, ordering, or_equal, number) => match field {
+                NumberField::Year => track.year.map(u64::from),
+                NumberField::Track => track.track_number.map(u64::from),
+                NumberField::Disc => track.disc_number.map(u64::from),
+                NumberField::Duration => track.duration.map(|duration| duration.as_secs()),
+            }
+            .is_some_and(|value| {
+                let result = value.cmp(number);
+                result == *ordering || (*or_equal && result == Ordering::Equal)
+            }),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some(&(_, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                let (term, quoted) = read_term(&mut chars, s)?;
+                // Quoting a keyword searches for the word itself
+                tokens.push(match term.as_str() {
+                    "AND" if !quoted => Token::And,
+                    "OR" if !quoted => Token::Or,
+                    "NOT" if !quoted => Token::Not,
+                    _ => Token::Term(term),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads a term up to unquoted whitespace or parenthesis, removing quotes, and whether any of it was quoted
+fn read_term(chars: &mut Peekable<CharIndices>, s: &str) -> Result<(String, bool), String> {
+    let mut term = String::new();
+    let mut quoted = false;
+    while let Some(&(index, c)) = chars.peek() {
+        match c {
+            '"' => {
+                chars.next();
+                quoted = true;
+                loop {
+                    match chars.next() {
+                        None => return Err(format!("Unterminated quote at {} in {}", index, s)),
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => {
+                            if let Some((_, c)) = chars.next() {
+                                term.push(c);
+                            }
+                        }
+                        Some((_, c)) => term.push(c),
+                    }
+                }
+            }
+            c if c.is_whitespace() || c == '(' || c == ')' => break,
+            c => {
+                chars.next();
+                term.push(c);
+            }
+        }
+    }
+    Ok((term, quoted))
+}
+
+type Tokens = Peekable<std::vec::IntoIter<Token>>;
+
+fn parse_expression(tokens: &mut Tokens) -> Result<Expression, String> {
+    let mut expression = parse_or(tokens)?;
+    while tokens.next_if_eq(&Token::Not).is_some() {
+        let excluded = parse_or(tokens)?;
+        expression = Expression::And(
+            Box::new(expression),
+            Box::new(Expression::Not(Box::new(excluded))),
+        );
+    }
+    Ok(expression)
+}
+
+fn parse_or(tokens: &mut Tokens) -> Result<Expression, String> {
+    let mut expression = parse_and(tokens)?;
+    while tokens.next_if_eq(&Token::Or).is_some() {
+        expression = Expression::Or(Box::new(expression), Box::new(parse_and(tokens)?));
+    }
+    Ok(expression)
+}
+
+fn parse_and(tokens: &mut Tokens) -> Result<Expression, String> {
+    let mut expression = parse_unary(tokens)?;
+    loop {
+        if tokens.next_if_eq(&Token::And).is_none()
+            && !matches!(tokens.peek(), Some(Token::Open | Token::Term(_)))
+        {
+            return Ok(expression);
+        }
+        expression = Expression::And(Box::new(expression), Box::new(parse_unary(tokens)?));
+    }
+}
+
+fn parse_unary(tokens: &mut Tokens) -> Result<Expression, String> {
+    match tokens.next() {
+        Some(Token::Not) => Ok(Expression::Not(Box::new(parse_unary(tokens)?))),
+        Some(Token::Open) => {
+            let expression = parse_expression(tokens)?;
+            match tokens.next() {
+                Some(Token::Close) => Ok(expression),
+                _ => Err("Missing )".to_string()),
+            }
+        }
+        Some(Token::Term(term)) => parse_term(&term),
+        Some(token) => Err(format!("Unexpected {:?}", token)),
+        None => Err("Query ended unexpectedly".to_string()),
+    }
+}
+
+fn parse_term(term: &str) -> Result<Expression, String> {
+    if let Some(index) = term.find(['<', '>', '=', ':']) {
+        let (field, rest) = term.split_at(index);
+        let number_field = match field.to_ascii_lowercase().as_str() {
+            "year" => Some(NumberField::Year),
+            "track" => Some(NumberField::Track),
+            "disc" => Some(NumberField::Disc),
+            "duration" => Some(NumberField::Duration),
+            _ => None,
+        };
+        if let Some(field) = number_field {
+            let (ordering, or_equal, number) = if let Some(number) = rest.strip_prefix("<=") {
+                (Ordering::Less, true, number)
+            } else if let Some(number) = rest.strip_prefix(">=") {
+                (Ordering::Greater, true, number)
+            } else if let Some(number) = rest.strip_prefix('<') {
+                (Ordering::Less, false, number)
+            } else if let Some(number) = rest.strip_prefix('>') {
+                (Ordering::Greater, false, number)
+            } else {
+                (Ordering::Equal, false, &rest[1..])
+            };
+            let number = number
+                .parse()
+                .map_err(|_| format!("Expected a number in {}", term))?;
+            return Ok(Expression::Number(field, ordering, or_equal, number));
+        }
+        if let Some(pattern) = rest.strip_prefix(':') {
+            let field = match field.to_ascii_lowercase().as_str() {
+                "path" => TextField::Path,
+                "title" => TextField::Title,
+                "artist" => TextField::Artist,
+                "album" => TextField::Album,
+                "genre" => TextField::Genre,
+                "codec" => TextField::Codec,
+                _ => return Err(format!("Unknown field {} in {}", field, term)),
+            };
+            return glob(field, pattern, term);
+        }
+    }
+    glob(TextField::Path, term, term)
+}
+
+fn glob(field: TextField, pattern: &str, term: &str) -> Result<Expression, String> {
+    let pattern = GlobBuilder::new(pattern)
+        .case_insensitive(true)
+        .literal_separator(field == TextField::Path)
+        .build()
+        .map_err(|e| format!("Bad pattern in {}: {}", term, e))?
+        .compile_matcher();
+    Ok(Expression::Text(field, pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn track(path: &str, artist: &str, title: &str, year: u32) -> Track {
+        Track {
+            path: Arc::from(Path::new(path)),
+            size: 0,
+            modified: None,
+            title: Some(title.to_string()),
+            artist: Some(artist.to_string()),
+            album: None,
+            genre: None,
+            track_number: None,
+            disc_number: None,
+            year: Some(year),
+            duration: Some(Duration::from_secs(200)),
+            codec: None,
+            sample_rate: None,
+            span: None,
+        }
+    }
+
+    /// Which of a few songs the query picks out
+    fn matching(query: &str) -> Vec<&'static str> {
+        let filter: Filter = query.parse().unwrap();
+        [
+            ("/music/Jazz/so what.flac", "Miles Davis", "So What", 1959),
+            (
+                "/music/Jazz/Live/round midnight.flac",
+                "Miles Davis",
+                "'Round Midnight",
+                1967,
+            ),
+            (
+                "/music/Pop/one more time.mp3",
+                "Daft Punk",
+                "One More Time",
+                2000,
+            ),
+            ("/music/Pop/or.mp3", "Or", "AND", 2010),
+        ]
+        .into_iter()
+        .filter(|(path, artist, title, year)| {
+            filter.matches(Path::new("/music"), &track(path, artist, title, *year))
+        })
+        .map(|(_, _, title, _)| title)
+        .collect()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            matching("artist:daft* OR Jazz/** year>1960"),
+            ["'Round Midnight", "One More Time"]
+        );
+        assert_eq!(
+            matching("(artist:daft* OR Jazz/**) year>1960"),
+            ["'Round Midnight", "One More Time"]
+        );
+        assert_eq!(matching("year>1960 AND artist:miles*"), ["'Round Midnight"]);
+        assert_eq!(
+            matching("artist:miles* (year<1960 OR year>=2000)"),
+            ["So What"]
+        );
+    }
+
+    #[test]
+    fn not_between_terms_excludes_everything_after() {
+        assert_eq!(matching("Jazz/** NOT Jazz/Live/**"), ["So What"]);
+        assert_eq!(matching("year>0 NOT artist:miles* OR title:one*"), ["AND"]);
+        assert_eq!(matching("NOT artist:miles* year<2005"), ["One More Time"]);
+        assert_eq!(matching("NOT NOT artist:or"), ["AND"]);
+    }
+
+    #[test]
+    fn quotes_keep_spaces_and_keywords() {
+        assert_eq!(matching(r#"artist:"Daft Punk""#), ["One More Time"]);
+        assert_eq!(matching(r#"title:"so what""#), ["So What"]);
+        assert_eq!(matching(r#"title:"AND""#), ["AND"]);
+        assert_eq!(matching(r#"artist:"OR""#), ["AND"]);
+        assert_eq!(matching(r#"Pop/"or".mp3"#), ["AND"]);
+        assert_eq!(matching(r#"title:"\'round*""#), ["'Round Midnight"]);
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_root() {
+        assert_eq!(matching(r#""*/so what.flac""#), ["So What"]);
+        assert_eq!(matching("Jazz/*"), ["So What"]);
+        assert_eq!(matching("path:jazz/**"), ["So What", "'Round Midnight"]);
+        assert!(matching("/music/**").is_empty());
+    }
+
+    #[test]
+    fn compares_numbers() {
+        assert_eq!(matching("year=1959"), ["So What"]);
+        assert_eq!(matching("year:1967"), ["'Round Midnight"]);
+        assert_eq!(matching("year<=1967 year>1959"), ["'Round Midnight"]);
+        assert_eq!(matching("duration>199 duration<201 year>2005"), ["AND"]);
+    }
+
+    #[test]
+    fn keeps_the_query_text() {
+        let query = r#"artist:"Daft Punk" OR (genre:jazz AND year<1970)"#;
+        assert_eq!(query.parse::<Filter>().unwrap().to_string(), query);
+    }
+
+    #[test]
+    fn explains_bad_queries() {
+        let error = |query: &str| query.parse::<Filter>().unwrap_err();
+        assert_eq!(
+            error(r#"artist:"Daft"#),
+            r#"Unterminated quote at 7 in artist:"Daft"#
+        );
+        assert_eq!(error("(year<1970"), "Missing )");
+        assert_eq!(error("year<1970 AND"), "Query ended unexpectedly");
+        assert_eq!(error("NOT"), "Query ended unexpectedly");
+        assert_eq!(error("OR jazz"), "Unexpected Or");
+        assert_eq!(error("jazz)"), "Unexpected Close in jazz)");
+        assert_eq!(error("mood:happy"), "Unknown field mood in mood:happy");
+        assert_eq!(error("year<old"), "Expected a number in year<old");
+        assert!(error("title:[a").starts_with("Bad pattern in title:[a: "));
+    }
+}
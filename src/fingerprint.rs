@@ -0,0 +1,128 @@
+use crate::decoder::DecodedStream;
+use crate::track::Track;
+use realfft::RealFftPlanner;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use symphonia::core::audio::Signal;
+
+const SAMPLE_RATE: usize = 44100;
+const LENGTH: usize = 30 * SAMPLE_RATE;
+const FRAME: usize = 8192;
+const HOP: usize = 4096;
+const BANDS: usize = 33;
+const LOW: f32 = 300.0;
+const HIGH: f32 = 2000.0;
+const MAX_OFFSET: isize = 3;
+
+/// A coarse acoustic fingerprint: each frame is 32 bits recording which of each pair of adjacent frequency bands
+/// is louder. Re-encoding a recording flips few bits; different recordings flip far more of them.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Fingerprint(Vec<u32>);
+
+impl Fingerprint {
+    /// Only decodes the start of a song, which says nothing about whether it plays, so failures are logged but kept
+    /// out of the quarantine
+    pub fn compute(track: Arc<Track>) -> Option<Self> {
+        let mut samples = Vec::with_capacity(LENGTH);
+        for buffer in futures::executor::block_on_stream(DecodedStream::new(track, None)) {
+            samples.extend(
+                buffer
+                    .chan(0)
+                    .iter()
+                    .zip(buffer.chan(1))
+                    .map(|(&left, &right)| (left as f32 + right as f32) / 2.0),
+            );
+            if samples.len() >= LENGTH {
+                samples.truncate(LENGTH);
+                break;
+            }
+        }
+        if samples.len() < FRAME * 2 {
+            return None;
+        }
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FRAME);
+        let mut input = fft.make_input_vec();
+        let mut spectrum = fft.make_output_vec();
+        let edges: Vec<usize> = (0..=BANDS)
+            .map(|band| {
+                let frequency = LOW * (HIGH / LOW).powf(band as f32 / BANDS as f32);
+                (frequency * FRAME as f32 / SAMPLE_RATE as f32).round() as usize
+            })
+            .collect();
+        let mut frames = Vec::new();
+        for window in samples.windows(FRAME).step_by(HOP) {
+            input.copy_from_slice(window);
+            if fft.process(&mut input, &mut spectrum).is_err() {
+                return None;
+            }
+            let energies: Vec<f32> = edges
+                .windows(2)
+                .map(|edge| {
+                    spectrum[edge[0]..edge[1].max(edge[0] + 1)]
+                        .iter()
+                        .map(|c| c.norm_sqr())
+                        .sum()
+                })
+                .collect();
+            let mut bits = 0u32;
+            for band in 0..BANDS - 1 {
+                if energies[band] > energies[band + 1] {
+                    bits |= 1 << band;
+                }
+            }
+            frames.push(bits);
+        }
+        Some(Fingerprint(frames))
+    }
+
+    /// The fraction of differing bits at the best alignment, allowing for small differences in encoder delay
+    pub fn distance(&self, other: &Fingerprint) -> f32 {
+        (-MAX_OFFSET..=MAX_OFFSET)
+            .filter_map(|offset| {
+                let (left, right) = if offset < 0 {
+                    (&self.0[..], other.0.get(offset.unsigned_abs()..)?)
+                } else {
+                    (self.0.get(offset as usize..)?, &other.0[..])
+                };
+                let length = left.len().min(right.len());
+                if length == 0 {
+                    return None;
+                }
+                let differing: u32 = left
+                    .iter()
+                    .zip(right)
+                    .map(|(left, right)| (left ^ right).count_ones())
+                    .sum();
+                Some(differing as f32 / (length * 32) as f32)
+            })
+            .fold(1.0, f32::min)
+    }
+}
+
+impl From<Fingerprint> for String {
+    fn from(value: Fingerprint) -> Self {
+        value
+            .0
+            .iter()
+            .map(|frame| format!("{:08x}", frame))
+            .collect()
+    }
+}
+
+impl TryFrom<String> for Fingerprint {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if !value.is_ascii() || !value.len().is_multiple_of(8) {
+            return Err("Fingerprint is malformed".to_string());
+        }
+        (0..value.len())
+            .step_by(8)
+            .map(|start| {
+                u32::from_str_radix(&value[start..start + 8], 16).map_err(|e| e.to_string())
+            })
+            .collect::<Result<_, _>>()
+            .map(Fingerprint)
+    }
+}
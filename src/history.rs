@@ -0,0 +1,77 @@
+use crate::state;
+use crate::state::Persisted;
+use crate::track::Track;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Only this many of the most recent plays are kept
+const MAX_ENTRIES: usize = 10_000;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Play {
+    pub path: Arc<Path>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub started: u64,
+    /// The stream, station, query or player the song was played on
+    pub stream: String,
+    /// How many people were listening, if known
+    pub listeners: Option<usize>,
+}
+
+#[derive(Clone)]
+pub struct History {
+    plays: Persisted<VecDeque<Play>>,
+}
+
+impl History {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let plays = path
+            .as_deref()
+            .and_then(state::read::<VecDeque<Play>>)
+            .unwrap_or_default();
+        History {
+            plays: Persisted::new(path, plays),
+        }
+    }
+    pub fn record(&self, track: &Track, stream: &str, listeners: Option<usize>) {
+        let mut plays = self.plays.lock();
+        if plays.len() >= MAX_ENTRIES {
+            plays.pop_front();
+        }
+        plays.push_back(Play {
+            path: track.path.clone(),
+            title: track.title.clone(),
+            artist: track.artist.clone(),
+            album: track.album.clone(),
+            started: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            stream: stream.to_string(),
+            listeners,
+        });
+        self.plays.changed();
+    }
+    /// The total number of plays and a page of them, most recent first
+    pub fn page(&self, offset: usize, limit: usize) -> (usize, Vec<Play>) {
+        let plays = self.plays.lock();
+        (
+            plays.len(),
+            plays
+                .iter()
+                .rev()
+                .skip(offset)
+                .take(limit)
+                .cloned()
+                .collect(),
+        )
+    }
+    pub fn save(&self) {
+        self.plays.save();
+    }
+}
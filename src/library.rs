@@ -0,0 +1,90 @@
+use crate::duplicates::Duplicates;
+use crate::playlist_file::PlaylistFile;
+use crate::quarantine::Quarantine;
+use crate::track::Track;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+pub type Tracks = BTreeMap<Arc<Path>, Arc<Track>>;
+pub type Playlists = BTreeMap<Arc<Path>, Arc<PlaylistFile>>;
+/// The CUE sheets naming each file, whether or not the file is there yet
+pub type CueSheets = BTreeMap<Arc<Path>, BTreeSet<Arc<Path>>>;
+/// Files that couldn't be read as audio, with the size and modification time they had, so they aren't probed again
+/// until they change
+pub type Unplayable = BTreeMap<Arc<Path>, (u64, Option<SystemTime>)>;
+
+#[derive(Clone, Debug)]
+pub struct Root {
+    pub path: PathBuf,
+    pub weight: u32,
+}
+
+#[derive(Clone)]
+pub struct LibraryRoot {
+    pub root: Root,
+    pub tracks: Tracks,
+    pub playlists: Playlists,
+    pub cue_sheets: CueSheets,
+    pub unplayable: Unplayable,
+}
+
+pub struct Library {
+    pub roots: Vec<LibraryRoot>,
+    pub duplicates: Duplicates,
+    /// Incremented whenever the tracks or duplicates change, so derived lists know to be rebuilt
+    pub generation: u64,
+}
+
+impl FromStr for Root {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+            .rsplit_once(':')
+            .map(|(path, weight)| (path, weight.parse::<u32>()))
+        {
+            Some((_, Ok(0))) => Err(format!("Weight for {} must be positive", s)),
+            Some((path, Ok(weight))) if !path.is_empty() => Ok(Root {
+                path: PathBuf::from(path),
+                weight,
+            }),
+            _ => Ok(Root {
+                path: PathBuf::from(s),
+                weight: 1,
+            }),
+        }
+    }
+}
+
+impl Library {
+    /// Whether a better copy of the same recording is available to play instead
+    pub fn is_redundant(&self, track: &Track, quarantine: &Quarantine) -> bool {
+        self.duplicates.group(&track.path).is_some_and(|group| {
+            group.iter().any(|other| {
+                *other != track.path
+                    && !quarantine.is_quarantined(other)
+                    && self.get(other).is_some_and(|other| {
+                        (other.quality(), &other.path) > (track.quality(), &track.path)
+                    })
+            })
+        })
+    }
+    pub fn get(&self, path: &Path) -> Option<&Arc<Track>> {
+        self.roots.iter().find_map(|root| root.tracks.get(path))
+    }
+    /// The playlist with this name, unless more than one root has a playlist called that
+    pub fn playlist(&self, name: &str) -> Option<&Arc<PlaylistFile>> {
+        let mut matches = self.playlists().filter(|playlist| playlist.name == name);
+        let playlist = matches.next()?;
+        matches.next().is_none().then_some(playlist)
+    }
+    pub fn playlists(&self) -> impl Iterator<Item = &Arc<PlaylistFile>> {
+        self.roots.iter().flat_map(|root| root.playlists.values())
+    }
+    pub fn tracks(&self) -> impl Iterator<Item = &Arc<Track>> {
+        self.roots.iter().flat_map(|root| root.tracks.values())
+    }
+}
@@ -1,8 +1,11 @@
-use crate::SongList;
 use crate::decoder::DecodedStream;
 use crate::exit_filter::ExitFilter;
+use crate::history::History;
 use crate::pausable_stream::{PausableStream, PauseResume};
 use crate::playlist::Playlist;
+use crate::quarantine::Quarantine;
+use crate::resume::Player;
+use crate::skippable_stream::{Skip, SkippableStream};
 use alsa::pcm::{Access, Format, HwParams, State};
 use alsa::{Direction, PCM, ValueOr};
 use futures::StreamExt;
@@ -18,11 +21,14 @@ enum NextBuffer {
 }
 
 pub fn start(
-    songs: SongList,
+    playlist: Playlist,
+    quarantine: Quarantine,
+    history: History,
+    player: Player,
     exit: broadcast::Sender<()>,
     device: String,
     start_paused: bool,
-) -> Result<PauseResume, Box<dyn std::error::Error>> {
+) -> Result<(PauseResume, Skip), Box<dyn std::error::Error>> {
     let thread_name = format!("Player for {}", &device);
     let device = CString::new(device.into_bytes())?;
     let pcm = PCM::open(&device, Direction::Playback, false)?;
@@ -41,9 +47,17 @@ pub fn start(
     drop(hwp);
     drop(swp);
 
+    let start_paused = start_paused || player.state().paused;
+    let songs = playlist
+        .resume(player.clone())
+        .fuse()
+        .inspect(move |track| history.record(track, "local", None));
+    let (stream, skip) = SkippableStream::new(songs, move |track| {
+        DecodedStream::new(track, Some(quarantine.clone()))
+    });
     let (stream, pause_resume) = PausableStream::new(
-        Playlist::from(songs)
-            .flat_map(DecodedStream::from)
+        stream
+            .inspect(move |buffer| player.played(buffer.frames(), buffer.spec().rate))
             .map(NextBuffer::Buffer),
         start_paused,
         NextBuffer::Paused,
@@ -60,11 +74,11 @@ pub fn start(
         };
 
         for buffer in futures::executor::block_on_stream(stream) {
-            if pcm.state() == State::Setup {
-                if let Err(e) = pcm.prepare() {
-                    eprintln!("Failed to prepare to ALSA: {}", e);
-                    return;
-                }
+            if pcm.state() == State::Setup
+                && let Err(e) = pcm.prepare()
+            {
+                eprintln!("Failed to prepare to ALSA: {}", e);
+                return;
             }
             match buffer {
                 NextBuffer::Buffer(buffer) => {
@@ -83,24 +97,24 @@ pub fn start(
                             }
                         }
                     }
-                    if pcm.state() != State::Running {
-                        if let Err(e) = pcm.start() {
-                            eprintln!("Failed to start to ALSA: {}", e);
-                            return;
-                        }
+                    if pcm.state() != State::Running
+                        && let Err(e) = pcm.start()
+                    {
+                        eprintln!("Failed to start to ALSA: {}", e);
+                        return;
                     }
                 }
                 NextBuffer::Paused => {
-                    if pcm.state() == State::Running {
-                        if let Err(e) = pcm.drain() {
-                            eprintln!("Failed to start to ALSA: {}", e);
-                            return;
-                        }
+                    if pcm.state() == State::Running
+                        && let Err(e) = pcm.drain()
+                    {
+                        eprintln!("Failed to start to ALSA: {}", e);
+                        return;
                     }
                 }
             }
         }
     })?;
 
-    Ok(pause_resume)
+    Ok((pause_resume, skip))
 }
@@ -1,36 +1,80 @@
+mod archive;
+mod broadcaster;
+mod cache;
+mod config;
+mod cue_sheet;
 mod decoder;
+mod duplicates;
 mod encoder;
+mod exclusions;
 mod exit_filter;
+mod filter;
+mod fingerprint;
+mod history;
+mod library;
 mod local;
 mod pausable_stream;
 mod playlist;
+mod playlist_file;
+mod quarantine;
+mod queries;
+mod queue;
 mod rate_limited_stream;
+mod resume;
 mod scanner;
+mod schedule;
+mod sessions;
+mod skippable_stream;
+mod state;
+mod stats;
+mod track;
 
+use crate::broadcaster::Broadcaster;
+use crate::config::Config;
 use crate::decoder::DecodedStream;
 use crate::encoder::EncodedStream;
 use crate::exit_filter::ExitFilter;
+use crate::history::History;
+use crate::library::{Library, Root};
 use crate::pausable_stream::PauseResume;
-use crate::playlist::Playlist;
+use crate::playlist::{Mode, Playlist, Source};
+use crate::quarantine::Quarantine;
+use crate::queries::Queries;
+use crate::queue::Queue;
 use crate::rate_limited_stream::RateLimitedStream;
+use crate::resume::Resume;
+use crate::sessions::{Session, Sessions};
+use crate::skippable_stream::Skip;
+use crate::stats::{Stats, Weighting};
 use clap::Parser;
 use futures::future::BoxFuture;
-use futures::{FutureExt, StreamExt};
+use futures::{FutureExt, Stream, StreamExt};
 use http_body_util::{Full, StreamBody};
 use hyper::body::{Body, Bytes, Frame, Incoming};
-use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, SET_COOKIE};
 use hyper::service::Service;
 use hyper::{Method, Request, Response, StatusCode, http};
 use hyper_util::rt::TokioIo;
-use std::collections::BTreeSet;
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
+use serde::Serialize;
 use std::convert::Infallible;
+use std::io::Read;
 use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+use symphonia::core::io::MediaSource;
 use tokio::net::TcpListener;
 use tokio::sync::{RwLock, broadcast};
 
-type SongList = Arc<RwLock<BTreeSet<Arc<Path>>>>;
+type SongList = Arc<RwLock<Library>>;
+
+const HISTORY_PAGE: usize = 50;
+const MAX_HISTORY_PAGE: usize = 1000;
+/// How much of a file is read at a time when sending it
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+/// The query parameters that choose what a stream plays
+const STREAM_OPTIONS: &[&str] = &["query", "mode", "gap", "seed"];
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Arguments {
@@ -40,15 +84,92 @@ struct Arguments {
     start_paused: bool,
     #[arg(short, long)]
     port: u16,
-    #[arg(value_name = "DIRECTORY")]
-    path: PathBuf,
+    /// Directory for persistent state (defaults to the XDG state directory)
+    #[arg(long)]
+    state_dir: Option<PathBuf>,
+    /// Library cache file (defaults to library.json in the state directory)
+    #[arg(long)]
+    cache: Option<PathBuf>,
+    /// Do not read or write a library cache
+    #[arg(long, conflicts_with = "cache")]
+    no_cache: bool,
+    /// Skip files matching this gitignore-style pattern; may be repeated
+    #[arg(long = "exclude", value_name = "GLOB")]
+    excludes: Vec<String>,
+    /// Stop playing a file after it fails to decode this many times (0 to never stop)
+    #[arg(long, default_value_t = 3)]
+    max_failures: u32,
+    /// A JSON file defining named stations
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Fingerprint the library in the background to find duplicate recordings, which decodes the start of every
+    /// song once
+    #[arg(long)]
+    duplicates: bool,
+    /// Treat recordings as duplicates when this fraction of their fingerprints differ or less
+    #[arg(long, default_value_t = 0.15)]
+    duplicate_threshold: f32,
+    /// In spread mode, the fewest songs to play between songs by the same artist or from the same album
+    #[arg(long, default_value_t = playlist::DEFAULT_GAP)]
+    spread_gap: usize,
+    /// How the local player picks songs: shuffle, spread, weighted or album
+    #[arg(long, default_value = "shuffle")]
+    local_mode: Mode,
+    /// Play one shared stream to every listener of /stream.mp3, picking songs in this mode; listeners asking for a
+    /// mode or query still get their own stream
+    #[arg(
+        long,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "shuffle"
+    )]
+    broadcast: Option<Mode>,
+    /// Shuffle in the same order every time; streams can override this with their own seed parameter
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Keep a disconnected listener's place in their stream for this many seconds, so they carry on if they
+    /// reconnect (0 to always start afresh); listeners are known by a cookie, or a session parameter for
+    /// players that don't keep cookies
+    #[arg(long, default_value_t = 300)]
+    session_grace: u64,
+    /// In weighted mode, raise ratings (1 to 5, unrated songs count as 2.5) to this power
+    #[arg(long, default_value_t = 1.0)]
+    rating_exponent: f64,
+    /// In weighted mode, divide by one more than the play count raised to this power
+    #[arg(long, default_value_t = 0.5)]
+    plays_exponent: f64,
+    /// In weighted mode, a song that just played recovers half its weight every this many hours (0 to ignore)
+    #[arg(long, default_value_t = 24.0)]
+    recency_half_life: f64,
+    #[arg(
+        value_name = "DIRECTORY[:WEIGHT]",
+        required_unless_present = "extra_roots"
+    )]
+    roots: Vec<Root>,
+    /// Additional music directory, optionally weighted relative to the others
+    #[arg(long = "root", id = "extra_roots", value_name = "DIRECTORY[:WEIGHT]")]
+    extra_roots: Vec<Root>,
 }
 
 #[derive(Clone)]
 struct Songs {
     songs: SongList,
+    quarantine: Quarantine,
+    stats: Stats,
+    history: History,
+    queries: Queries,
+    local_queue: Option<Queue>,
+    broadcast_queue: Option<Queue>,
+    config: Arc<Config>,
     exit: broadcast::Sender<()>,
     local_player: Option<PauseResume>,
+    resume: Resume,
+    local_skip: Option<Skip>,
+    broadcaster: Option<Broadcaster>,
+    spread_gap: usize,
+    seed: Option<u64>,
+    sessions: Sessions,
 }
 type BoxedBody = Box<dyn Body<Data = Bytes, Error = Infallible> + Unpin + Send + 'static>;
 impl Service<Request<Incoming>> for Songs {
@@ -59,8 +180,21 @@ impl Service<Request<Incoming>> for Songs {
     fn call(&self, req: Request<Incoming>) -> Self::Future {
         let Songs {
             songs,
+            quarantine,
+            stats,
+            history,
+            queries,
+            local_queue,
+            broadcast_queue,
+            config,
             exit,
             local_player,
+            resume,
+            local_skip,
+            broadcaster,
+            spread_gap,
+            seed,
+            sessions,
         } = self.clone();
         async move {
             match (req.method(), req.uri().path(), local_player) {
@@ -76,31 +210,440 @@ impl Service<Request<Incoming>> for Songs {
                         Box::new(Full::new(Bytes::from(&include_bytes!("note.svg")[..])))
                             as BoxedBody,
                     ),
+                (&Method::GET, "/stream.mp3", _)
+                    if !has_stream_options(&req)
+                        && let Some(broadcaster) = broadcaster =>
+                {
+                    Response::builder()
+                        .header(CONTENT_TYPE, "audio/mp3")
+                        .header(CACHE_CONTROL, "no-cache")
+                        .body(Box::new(StreamBody::new(
+                            ExitFilter::new(exit, broadcaster.listen())
+                                .map(|data| Ok(Frame::data(data))),
+                        )) as BoxedBody)
+                }
                 (&Method::GET, "/stream.mp3", _) => {
-                    match EncodedStream::new(ExitFilter::new(
-                        exit,
-                        RateLimitedStream::new(Playlist::from(songs).flat_map(DecodedStream::from)),
-                    )) {
-                        Ok(stream) => Response::builder()
-                            .header(CONTENT_TYPE, "audio/mp3")
-                            .header(CACHE_CONTROL, "no-cache")
-                            .body(Box::new(StreamBody::new(
-                                stream.map(|data| Ok(Frame::data(data))),
-                            )) as BoxedBody),
-                        Err(_) => Response::builder()
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .body(Box::new(Full::new(Bytes::from(
-                                "Failed to initalise audio encoder",
-                            ))) as BoxedBody),
+                    let source = match query_parameter(&req, "query") {
+                        Some(query) => query.parse().map(|filter| Source::Filter(Arc::new(filter))),
+                        None => Ok(Source::Library),
+                    };
+                    match source.and_then(|source| {
+                        Ok((
+                            source,
+                            stream_options(&req, Mode::Shuffle, spread_gap, seed)?,
+                        ))
+                    }) {
+                        Ok((source, (mode, seed))) => stream(
+                            Playlist::new(
+                                songs,
+                                source,
+                                mode,
+                                quarantine.clone(),
+                                stats,
+                                None,
+                                seed,
+                            ),
+                            sessions.session(&req),
+                            history,
+                            quarantine,
+                            exit,
+                        ),
+                        Err(e) => message(StatusCode::BAD_REQUEST, e),
+                    }
+                }
+                (&Method::GET, "/stations", _) => {
+                    let library = songs.read().await;
+                    // Playlist files in the library are stations too, unless one is configured with their name
+                    let playlists = library
+                        .playlists()
+                        .filter(|playlist| !config.stations.contains_key(&playlist.name))
+                        .map(|playlist| StationInfo {
+                            name: &playlist.name,
+                            filter: None,
+                            query: None,
+                            playlist: Some(PlaylistInfo {
+                                path: &playlist.path,
+                                entries: playlist.entries.len(),
+                            }),
+                            mode: Mode::Ordered.to_string(),
+                            url: station_url(&playlist.name),
+                        });
+                    json(
+                        &config
+                            .stations
+                            .values()
+                            .map(|station| StationInfo {
+                                name: &station.name,
+                                filter: station.filter.as_ref().map(|filter| filter.to_string()),
+                                query: station.query.as_deref(),
+                                playlist: None,
+                                mode: station.mode.to_string(),
+                                url: station_url(&station.name),
+                            })
+                            .chain(playlists)
+                            .collect::<Vec<_>>(),
+                    )
+                }
+                (&Method::GET, path, _)
+                    if path.starts_with("/stations/")
+                        && path.ends_with(".mp3")
+                        && !config.stations.contains_key(&station_name(path)) =>
+                {
+                    let name = station_name(path);
+                    let matches = songs
+                        .read()
+                        .await
+                        .playlists()
+                        .filter(|playlist| playlist.name == name)
+                        .count();
+                    match matches {
+                        0 => message(StatusCode::NOT_FOUND, "No such station".into()),
+                        1 => match stream_options(&req, Mode::Ordered, spread_gap, seed) {
+                            Ok((mode, seed)) => stream(
+                                Playlist::new(
+                                    songs,
+                                    Source::PlaylistFile(name),
+                                    mode,
+                                    quarantine.clone(),
+                                    stats,
+                                    None,
+                                    seed,
+                                ),
+                                sessions.session(&req),
+                                history,
+                                quarantine,
+                                exit,
+                            ),
+                            Err(e) => message(StatusCode::BAD_REQUEST, e),
+                        },
+                        // Roots can hold playlists with the same name, and there is no telling which was meant
+                        _ => message(
+                            StatusCode::CONFLICT,
+                            format!("Several playlists are called {}", name),
+                        ),
+                    }
+                }
+                (&Method::GET, path, _)
+                    if path.starts_with("/stations/") && path.ends_with(".mp3") =>
+                {
+                    let name = station_name(path);
+                    let station = config.stations.get(&name);
+                    let source = station.map(|station| match (&station.filter, &station.query) {
+                        (Some(filter), _) => Some(Source::Filter(filter.clone())),
+                        (None, Some(query)) => queries.get(query).map(Source::Filter),
+                        (None, None) => Some(Source::Library),
+                    });
+                    match station.zip(source) {
+                        None => message(StatusCode::NOT_FOUND, "No such station".into()),
+                        Some((_, None)) => {
+                            message(StatusCode::NOT_FOUND, "No such saved query".into())
+                        }
+                        Some((station, Some(source))) => {
+                            match stream_options(&req, station.mode, spread_gap, seed) {
+                                Ok((mode, seed)) => stream(
+                                    Playlist::new(
+                                        songs,
+                                        source,
+                                        mode,
+                                        quarantine.clone(),
+                                        stats,
+                                        None,
+                                        seed,
+                                    ),
+                                    sessions.session(&req),
+                                    history,
+                                    quarantine,
+                                    exit,
+                                ),
+                                Err(e) => message(StatusCode::BAD_REQUEST, e),
+                            }
+                        }
+                    }
+                }
+                (&Method::GET, "/queries", _) => {
+                    let library = songs.read().await;
+                    json(
+                        &queries
+                            .all()
+                            .iter()
+                            .map(|(name, filter)| QueryInfo {
+                                name,
+                                query: filter.to_string(),
+                                matches: library
+                                    .roots
+                                    .iter()
+                                    .map(|root| {
+                                        root.tracks
+                                            .values()
+                                            .filter(|track| filter.matches(&root.root.path, track))
+                                            .count()
+                                    })
+                                    .sum(),
+                                url: format!("queries/{}.mp3", utf8_percent_encode(name, URL_PATH)),
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                }
+                (&Method::PUT, "/queries", _) => {
+                    match (
+                        query_parameter(&req, "name"),
+                        query_parameter(&req, "query"),
+                    ) {
+                        (Some(name), Some(query)) if !name.is_empty() => match query.parse() {
+                            Ok(filter) => {
+                                queries.save(name, filter);
+                                json(&true)
+                            }
+                            Err(e) => message(StatusCode::BAD_REQUEST, e),
+                        },
+                        _ => message(StatusCode::BAD_REQUEST, "Missing name or query".into()),
+                    }
+                }
+                (&Method::DELETE, "/queries", _) => match query_parameter(&req, "name") {
+                    Some(name) => json(&queries.remove(&name)),
+                    None => message(StatusCode::BAD_REQUEST, "Missing name".into()),
+                },
+                (&Method::GET, path, _)
+                    if path.starts_with("/queries/") && path.ends_with(".mp3") =>
+                {
+                    let name = percent_decode_str(&path["/queries/".len()..path.len() - 4])
+                        .decode_utf8_lossy();
+                    match queries.get(&name) {
+                        None => message(StatusCode::NOT_FOUND, "No such saved query".into()),
+                        Some(filter) => match stream_options(&req, Mode::Shuffle, spread_gap, seed)
+                        {
+                            Ok((mode, seed)) => stream(
+                                Playlist::new(
+                                    songs,
+                                    Source::Filter(filter),
+                                    mode,
+                                    quarantine.clone(),
+                                    stats,
+                                    None,
+                                    seed,
+                                ),
+                                sessions.session(&req),
+                                history,
+                                quarantine,
+                                exit,
+                            ),
+                            Err(e) => message(StatusCode::BAD_REQUEST, e),
+                        },
+                    }
+                }
+                (&Method::GET | &Method::POST | &Method::PUT | &Method::DELETE, "/queue", _) => {
+                    match player_queue(&req, &local_queue, &broadcast_queue) {
+                        Err((status, text)) => message(status, text),
+                        Ok(queue) => match *req.method() {
+                            Method::GET => json(&queue.entries()),
+                            Method::POST => {
+                                let position =
+                                    match query_parameter(&req, "position").map(|p| p.parse()) {
+                                        None => Ok(None),
+                                        Some(Ok(position)) => Ok(Some(position)),
+                                        Some(Err(_)) => Err("Invalid position".to_string()),
+                                    };
+                                match (query_parameter(&req, "path"), position) {
+                                    (None, _) => {
+                                        message(StatusCode::BAD_REQUEST, "Missing path".into())
+                                    }
+                                    (_, Err(e)) => message(StatusCode::BAD_REQUEST, e),
+                                    (Some(path), Ok(position)) => {
+                                        // A directory queues everything in it, in path order
+                                        let tracks: Vec<_> = songs
+                                            .read()
+                                            .await
+                                            .tracks()
+                                            .filter(|track| track.path.starts_with(&path))
+                                            .cloned()
+                                            .collect();
+                                        if tracks.is_empty() {
+                                            message(
+                                                StatusCode::NOT_FOUND,
+                                                "No such song or directory".into(),
+                                            )
+                                        } else {
+                                            json(&queue.add(tracks, position))
+                                        }
+                                    }
+                                }
+                            }
+                            Method::PUT => {
+                                match (
+                                    query_parameter(&req, "id").and_then(|id| id.parse().ok()),
+                                    query_parameter(&req, "position").and_then(|p| p.parse().ok()),
+                                ) {
+                                    (Some(id), Some(position)) => {
+                                        if queue.move_to(id, position) {
+                                            json(&queue.entries())
+                                        } else {
+                                            message(
+                                                StatusCode::NOT_FOUND,
+                                                "No such queue entry".into(),
+                                            )
+                                        }
+                                    }
+                                    _ => message(
+                                        StatusCode::BAD_REQUEST,
+                                        "Missing id or position".into(),
+                                    ),
+                                }
+                            }
+                            _ => match query_parameter(&req, "id") {
+                                None => {
+                                    queue.clear();
+                                    json(&true)
+                                }
+                                Some(id) => match id.parse() {
+                                    Ok(id) => json(&queue.remove(id)),
+                                    Err(_) => message(StatusCode::BAD_REQUEST, "Invalid id".into()),
+                                },
+                            },
+                        },
+                    }
+                }
+                (&Method::GET, "/history", _) => {
+                    let offset = query_parameter(&req, "offset").and_then(|o| o.parse().ok());
+                    let limit = query_parameter(&req, "limit").and_then(|l| l.parse().ok());
+                    let (total, plays) = history.page(
+                        offset.unwrap_or(0),
+                        limit.unwrap_or(HISTORY_PAGE).min(MAX_HISTORY_PAGE),
+                    );
+                    json(&HistoryPage { total, plays })
+                }
+                (&Method::GET, "/history.html", _) => Response::builder()
+                    .header(CONTENT_TYPE, "text/html;charset=UTF-8")
+                    .body(
+                        Box::new(Full::new(Bytes::from(&include_bytes!("history.html")[..])))
+                            as BoxedBody,
+                    ),
+                (&Method::GET, "/file", _) => {
+                    // Only files in the library can be fetched; a CUE sheet track is the whole file it is cut from
+                    let track = match query_parameter(&req, "path") {
+                        Some(path) => songs.read().await.get(Path::new(&path)).cloned(),
+                        None => None,
+                    };
+                    match track {
+                        None => message(StatusCode::NOT_FOUND, "No such song".into()),
+                        Some(track) => {
+                            let file = track.file().clone();
+                            let source =
+                                tokio::task::spawn_blocking(move || archive::open(&file)).await;
+                            match source {
+                                Ok(Ok(source)) => {
+                                    let mut response = Response::builder().header(
+                                        CONTENT_TYPE,
+                                        mime_guess::from_path(track.file())
+                                            .first_or_octet_stream()
+                                            .as_ref(),
+                                    );
+                                    if let Some(length) = source.byte_len() {
+                                        response = response.header(CONTENT_LENGTH, length);
+                                    }
+                                    response.body(Box::new(StreamBody::new(
+                                        read_chunks(source).map(|data| Ok(Frame::data(data))),
+                                    ))
+                                        as BoxedBody)
+                                }
+                                Ok(Err(e)) => {
+                                    message(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+                                }
+                                Err(e) => message(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                            }
+                        }
+                    }
+                }
+                (&Method::GET, "/duplicates", _) => {
+                    let library = songs.read().await;
+                    json(
+                        &library
+                            .duplicates
+                            .groups()
+                            .iter()
+                            .map(|group| {
+                                let mut tracks: Vec<_> =
+                                    group.iter().filter_map(|path| library.get(path)).collect();
+                                tracks.sort_by(|a, b| {
+                                    (b.quality(), &b.path).cmp(&(a.quality(), &a.path))
+                                });
+                                tracks
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                }
+                (&Method::GET, "/stats", _) => json(&stats.stats()),
+                (&Method::PUT | &Method::DELETE, "/rating", _) => {
+                    let rating = match (req.method(), query_parameter(&req, "rating")) {
+                        (&Method::DELETE, _) => Ok(None),
+                        (_, Some(rating)) => match rating.parse() {
+                            Ok(rating @ 1..=stats::MAX_RATING) => Ok(Some(rating)),
+                            _ => Err(format!("Invalid rating {}", rating)),
+                        },
+                        (_, None) => Err("Missing rating".to_string()),
+                    };
+                    match (query_parameter(&req, "path"), rating) {
+                        (None, _) => message(StatusCode::BAD_REQUEST, "Missing path".into()),
+                        (_, Err(e)) => message(StatusCode::BAD_REQUEST, e),
+                        (Some(path), Ok(rating)) => {
+                            let path: Arc<Path> = Path::new(&path).into();
+                            if songs.read().await.get(&path).is_none() {
+                                message(StatusCode::NOT_FOUND, "No such song".into())
+                            } else {
+                                stats.set_rating(&path, rating);
+                                json(&rating)
+                            }
+                        }
                     }
                 }
+                (&Method::GET, "/quarantine", _) => json(&quarantine.failures()),
+                (&Method::DELETE, "/quarantine", _) => match query_parameter(&req, "path") {
+                    Some(path) => json(&quarantine.clear(Path::new(&path))),
+                    None => message(StatusCode::BAD_REQUEST, "Missing path".into()),
+                },
+                (&Method::GET, "/skip", _) => json(&SkipInfo {
+                    local: local_skip.is_some(),
+                    broadcast: broadcaster.is_some(),
+                }),
+                (&Method::POST, "/skip", _) => match query_parameter(&req, "player").as_deref() {
+                    None => {
+                        if let Some(skip) = &local_skip {
+                            skip.skip();
+                        }
+                        if let Some(broadcaster) = &broadcaster {
+                            broadcaster.skip();
+                        }
+                        json(&(local_skip.is_some() || broadcaster.is_some()))
+                    }
+                    Some("local") => match local_skip {
+                        Some(skip) => {
+                            skip.skip();
+                            json(&true)
+                        }
+                        None => message(StatusCode::NOT_FOUND, "No local player".into()),
+                    },
+                    Some("broadcast") => match broadcaster {
+                        Some(broadcaster) => {
+                            broadcaster.skip();
+                            json(&true)
+                        }
+                        None => message(StatusCode::NOT_FOUND, "Not broadcasting".into()),
+                    },
+                    Some(player) => message(
+                        StatusCode::BAD_REQUEST,
+                        format!("Unknown player {}", player),
+                    ),
+                },
                 (_, "/local", None) => Response::builder()
                     .header(CONTENT_TYPE, "application/json")
                     .status(StatusCode::OK)
                     .body(Box::new(Full::new(Bytes::from("null"))) as BoxedBody),
                 (method, "/local", Some(local_player)) => {
                     let is_paused = match method {
-                        &Method::POST => local_player.pause_resume(),
+                        &Method::POST => {
+                            let is_paused = local_player.pause_resume();
+                            resume.player("local").set_paused(is_paused);
+                            is_paused
+                        }
                         _ => local_player.is_paused(),
                     };
                     Response::builder()
@@ -121,30 +664,346 @@ impl Service<Request<Incoming>> for Songs {
     }
 }
 
+#[derive(Serialize)]
+struct StationInfo<'a> {
+    name: &'a str,
+    filter: Option<String>,
+    query: Option<&'a str>,
+    playlist: Option<PlaylistInfo<'a>>,
+    mode: String,
+    url: String,
+}
+
+#[derive(Serialize)]
+struct HistoryPage {
+    total: usize,
+    plays: Vec<history::Play>,
+}
+
+#[derive(Serialize)]
+struct SkipInfo {
+    local: bool,
+    broadcast: bool,
+}
+
+#[derive(Serialize)]
+struct QueryInfo<'a> {
+    name: &'a str,
+    query: String,
+    matches: usize,
+    url: String,
+}
+
+#[derive(Serialize)]
+struct PlaylistInfo<'a> {
+    path: &'a Path,
+    entries: usize,
+}
+
+const URL_PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'?')
+    .add(b'<')
+    .add(b'>')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+fn stream(
+    playlist: Playlist,
+    session: Session,
+    history: History,
+    quarantine: Quarantine,
+    exit: broadcast::Sender<()>,
+) -> Result<Response<BoxedBody>, http::Error> {
+    let cookie = session.cookie();
+    let name = session.stream.clone();
+    let playlist = session.resume(playlist);
+    match EncodedStream::new(ExitFilter::new(
+        exit,
+        RateLimitedStream::new(
+            playlist
+                .fuse()
+                .inspect(move |track| history.record(track, &name, Some(1)))
+                .flat_map(move |track| DecodedStream::new(track, Some(quarantine.clone()))),
+        ),
+    )) {
+        Ok(stream) => {
+            let mut response = Response::builder();
+            if let Some(cookie) = cookie {
+                response = response.header(SET_COOKIE, cookie);
+            }
+            response
+                .header(CONTENT_TYPE, "audio/mp3")
+                .header(CACHE_CONTROL, "no-cache")
+                .body(
+                    Box::new(StreamBody::new(stream.map(|data| Ok(Frame::data(data)))))
+                        as BoxedBody,
+                )
+        }
+        Err(_) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(
+                Box::new(Full::new(Bytes::from("Failed to initalise audio encoder"))) as BoxedBody,
+            ),
+    }
+}
+
+/// A file's contents, read a piece at a time off the async threads
+fn read_chunks(source: Box<dyn MediaSource>) -> impl Stream<Item = Bytes> + Send + Unpin {
+    futures::stream::unfold(source, |mut source| async move {
+        let (source, buffer) = tokio::task::spawn_blocking(move || {
+            let mut buffer = vec![0; FILE_CHUNK_SIZE];
+            let read = source.read(&mut buffer).map(|length| {
+                buffer.truncate(length);
+                buffer
+            });
+            (source, read)
+        })
+        .await
+        .ok()?;
+        match buffer {
+            Ok(buffer) if buffer.is_empty() => None,
+            Ok(buffer) => Some((Bytes::from(buffer), source)),
+            Err(e) => {
+                eprintln!("Failed to read file: {}", e);
+                None
+            }
+        }
+    })
+    .boxed()
+}
+
+/// The mode and seed to play a stream with, from the request or else the defaults
+fn stream_options<B>(
+    req: &Request<B>,
+    default: Mode,
+    spread_gap: usize,
+    seed: Option<u64>,
+) -> Result<(Mode, Option<u64>), String> {
+    // The default keeps its own gap unless the request gives one
+    let mode = match query_parameter(req, "mode") {
+        None => default,
+        Some(mode) => mode.parse::<Mode>()?.with_gap(spread_gap),
+    };
+    let mode = match query_parameter(req, "gap") {
+        None => mode,
+        Some(gap) => mode.with_gap(gap.parse().map_err(|_| format!("Invalid gap {}", gap))?),
+    };
+    let seed = match query_parameter(req, "seed") {
+        None => seed,
+        Some(seed) => Some(seed.parse().map_err(|_| format!("Invalid seed {}", seed))?),
+    };
+    Ok((mode, seed))
+}
+
+/// The queue of the player named in the request, or the only player if there is just one
+fn player_queue<B>(
+    req: &Request<B>,
+    local: &Option<Queue>,
+    broadcast: &Option<Queue>,
+) -> Result<Queue, (StatusCode, String)> {
+    match query_parameter(req, "player").as_deref() {
+        None => match (local, broadcast) {
+            (Some(queue), None) | (None, Some(queue)) => Ok(queue.clone()),
+            (None, None) => Err((StatusCode::NOT_FOUND, "No player to queue for".into())),
+            (Some(_), Some(_)) => Err((
+                StatusCode::BAD_REQUEST,
+                "Missing player, which must be local or broadcast".into(),
+            )),
+        },
+        Some("local") => local
+            .clone()
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "No local player".into())),
+        Some("broadcast") => broadcast
+            .clone()
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "Not broadcasting".into())),
+        Some(player) => Err((
+            StatusCode::BAD_REQUEST,
+            format!("Unknown player {}", player),
+        )),
+    }
+}
+
+/// The station named in a `/stations/<name>.mp3` path
+fn station_name(path: &str) -> String {
+    percent_decode_str(&path["/stations/".len()..path.len() - 4])
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+fn station_url(name: &str) -> String {
+    format!("stations/{}.mp3", utf8_percent_encode(name, URL_PATH))
+}
+
+fn message(status: StatusCode, text: String) -> Result<Response<BoxedBody>, http::Error> {
+    Response::builder()
+        .status(status)
+        .body(Box::new(Full::new(Bytes::from(text))) as BoxedBody)
+}
+
+fn json<T: Serialize>(value: &T) -> Result<Response<BoxedBody>, http::Error> {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .status(StatusCode::OK)
+            .body(Box::new(Full::new(Bytes::from(body))) as BoxedBody),
+        Err(e) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Box::new(Full::new(Bytes::from(e.to_string()))) as BoxedBody),
+    }
+}
+
+/// Whether the request asks for its own selection of songs rather than whatever is playing
+fn has_stream_options<B>(req: &Request<B>) -> bool {
+    STREAM_OPTIONS
+        .iter()
+        .any(|option| query_parameter(req, option).is_some())
+}
+
+fn query_parameter<B>(req: &Request<B>, name: &str) -> Option<String> {
+    form_urlencoded::parse(req.uri().query()?.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let Arguments {
         local_device,
         port,
-        path: root_path,
+        mut roots,
+        extra_roots,
         start_paused,
+        state_dir,
+        cache,
+        no_cache,
+        excludes,
+        max_failures,
+        config,
+        duplicates,
+        duplicate_threshold,
+        spread_gap,
+        local_mode,
+        broadcast,
+        seed,
+        session_grace,
+        rating_exponent,
+        plays_exponent,
+        recency_half_life,
     } = Arguments::parse();
     let (exit_tx, mut exit_rx) = broadcast::channel(1);
-    let songs = scanner::create_scanner(root_path, &exit_tx).await?;
-
-    let local_player = match local_device {
-        Some(local_device) => Some(local::start(
+    let state_dir = state_dir.or_else(state::default_directory);
+    let cache = if no_cache {
+        None
+    } else {
+        cache.or_else(|| Some(state_dir.as_ref()?.join("library.json")))
+    };
+    let quarantine = Quarantine::new(
+        max_failures,
+        state_dir.as_ref().map(|dir| dir.join("quarantine.json")),
+    );
+    let stats = Stats::new(
+        state_dir.as_ref().map(|dir| dir.join("stats.json")),
+        Weighting {
+            rating_exponent,
+            plays_exponent,
+            recency_half_life,
+        },
+    );
+    let history = History::new(state_dir.as_ref().map(|dir| dir.join("history.json")));
+    let queries = Queries::new(state_dir.as_ref().map(|dir| dir.join("queries.json")));
+    let config = match config {
+        Some(path) => config::load(&path, &queries, spread_gap)?,
+        None => Config::default(),
+    };
+    roots.extend(extra_roots);
+    let (songs, scanner) = scanner::create_scanner(roots, &excludes, cache, &exit_tx).await?;
+    let fingerprinter = duplicates.then(|| {
+        duplicates::start(
             songs.clone(),
-            exit_tx.clone(),
-            local_device,
-            start_paused,
-        )?),
-        None => None,
+            quarantine.clone(),
+            state_dir.as_ref().map(|dir| dir.join("fingerprints.json")),
+            duplicate_threshold,
+            &exit_tx,
+        )
+    });
+
+    // The players follow the schedule, if there is one
+    let scheduled = || match &config.schedule {
+        Some(schedule) => Source::Schedule(schedule.clone()),
+        None => Source::Library,
+    };
+    let resume = Resume::new(state_dir.as_ref().map(|dir| dir.join("resume.json")));
+    let local_queue = local_device.as_ref().map(|_| Queue::default());
+    let (local_player, local_skip) = match local_device {
+        Some(local_device) => {
+            let (local_player, local_skip) = local::start(
+                Playlist::new(
+                    songs.clone(),
+                    scheduled(),
+                    local_mode.with_gap(spread_gap),
+                    quarantine.clone(),
+                    stats.clone(),
+                    local_queue.clone(),
+                    seed,
+                ),
+                quarantine.clone(),
+                history.clone(),
+                resume.player("local"),
+                exit_tx.clone(),
+                local_device,
+                start_paused,
+            )?;
+            (Some(local_player), Some(local_skip))
+        }
+        None => (None, None),
+    };
+    let broadcast_queue = broadcast.map(|_| Queue::default());
+    let (broadcaster, broadcasting) = match broadcast {
+        Some(mode) => {
+            let playlist = Playlist::new(
+                songs.clone(),
+                scheduled(),
+                mode.with_gap(spread_gap),
+                quarantine.clone(),
+                stats.clone(),
+                broadcast_queue.clone(),
+                seed,
+            );
+            match broadcaster::start(
+                playlist,
+                quarantine.clone(),
+                history.clone(),
+                resume.player("broadcast"),
+                &exit_tx,
+            ) {
+                Ok((broadcaster, broadcasting)) => (Some(broadcaster), Some(broadcasting)),
+                Err(()) => return Err("Failed to initalise audio encoder".into()),
+            }
+        }
+        None => (None, None),
     };
     let songs = Songs {
         songs,
+        quarantine: quarantine.clone(),
+        stats: stats.clone(),
+        history: history.clone(),
+        queries: queries.clone(),
+        local_queue,
+        broadcast_queue,
+        config: Arc::new(config),
         exit: exit_tx.clone(),
         local_player,
+        resume: resume.clone(),
+        local_skip,
+        broadcaster,
+        spread_gap,
+        seed,
+        sessions: Sessions::new(Duration::from_secs(session_grace)),
     };
     let listener =
         TcpListener::bind(&SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port)).await?;
@@ -182,6 +1041,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     eprintln!("Shutting down...");
 
     exit_tx.send(()).expect("Failed to shutdown");
+    if let Err(e) = scanner.await {
+        eprintln!("Failed to stop scanner: {}", e);
+    }
+    if let Some(fingerprinter) = fingerprinter
+        && let Err(e) = fingerprinter.await
+    {
+        eprintln!("Failed to stop fingerprinting: {}", e);
+    }
+    if let Some(broadcasting) = broadcasting
+        && let Err(e) = broadcasting.await
+    {
+        eprintln!("Failed to stop broadcast: {}", e);
+    }
+    resume.save();
+    quarantine.save();
+    stats.save();
+    history.save();
+    queries.flush();
 
     Ok(())
 }
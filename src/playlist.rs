@@ -1,52 +1,771 @@
 use crate::SongList;
+use crate::filter::Filter;
+use crate::library::Library;
+use crate::quarantine::Quarantine;
+use crate::queries::Queries;
+use crate::queue::Queue;
+use crate::resume::{Player, PlayerState};
+use crate::schedule::Schedule;
+use crate::stats::Stats;
+use crate::track::Track;
 use futures::future::BoxFuture;
 use futures::{FutureExt, Stream};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use std::collections::BTreeSet;
+use rand::{RngExt, SeedableRng};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::{Display, Formatter};
 use std::path::Path;
 use std::pin::Pin;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 use tokio::sync::OwnedRwLockReadGuard;
 
+pub enum Source {
+    Library,
+    Filter(Arc<Filter>),
+    /// A saved query, looked up whenever songs are picked so an edited query plays as it is now
+    Query(Queries, String),
+    PlaylistFile(String),
+    /// Whatever the schedule says to play at the time, or the whole library when it says nothing
+    Schedule(Arc<Schedule>),
+}
+
+pub const DEFAULT_GAP: usize = 3;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    Shuffle,
+    Ordered,
+    /// Shuffle, but keep at least this many other songs between songs by the same artist or from the same album
+    Spread(usize),
+    /// Pick every song afresh, favouring highly rated, rarely and not recently played songs
+    Weighted,
+    /// Shuffle albums, but play each album's songs in order
+    Album,
+}
+
+/// A root's weight and the songs left to play from it, with the library generation at which it last had nothing to
+/// play, so it isn't searched again until the library changes
+type RootSongs = (u32, Vec<Arc<Track>>, Option<u64>);
+
 pub struct Playlist {
-    current: Vec<Arc<Path>>,
+    current: Vec<RootSongs>,
     all: SongList,
-    waiting: Option<BoxFuture<'static, OwnedRwLockReadGuard<BTreeSet<Arc<Path>>>>>,
+    source: Source,
+    mode: Mode,
+    /// The mode to go back to when no schedule entry is active
+    default_mode: Mode,
+    /// The schedule entry the current songs were picked for
+    scheduled: Option<usize>,
+    quarantine: Quarantine,
+    stats: Stats,
+    /// Every random choice comes from here, so a seeded playlist always plays in the same order
+    rng: StdRng,
+    /// Requested songs to play before picking any
+    queue: Option<Queue>,
+    album: Option<(usize, String)>,
+    /// The library generation a filter was last evaluated against
+    generation: u64,
+    /// Where to record progress so playback can carry on after a restart
+    player: Option<Player>,
+    /// Saved progress to pick up from once the library is available
+    restore: Option<PlayerState>,
+    waiting: Option<BoxFuture<'static, OwnedRwLockReadGuard<Library>>>,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shuffle" => Ok(Mode::Shuffle),
+            "ordered" => Ok(Mode::Ordered),
+            "spread" => Ok(Mode::Spread(DEFAULT_GAP)),
+            "weighted" => Ok(Mode::Weighted),
+            "album" => Ok(Mode::Album),
+            _ => Err(format!("Unknown mode {}", s)),
+        }
+    }
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mode::Shuffle => f.write_str("shuffle"),
+            Mode::Ordered => f.write_str("ordered"),
+            Mode::Spread(_) => f.write_str("spread"),
+            Mode::Weighted => f.write_str("weighted"),
+            Mode::Album => f.write_str("album"),
+        }
+    }
+}
+
+impl Mode {
+    /// The same mode, but spreading songs by this gap if it spreads them at all
+    pub fn with_gap(self, gap: usize) -> Mode {
+        match self {
+            Mode::Spread(_) => Mode::Spread(gap),
+            mode => mode,
+        }
+    }
+    fn arrange(self, songs: &mut Vec<Arc<Track>>, rng: &mut StdRng) {
+        match self {
+            Mode::Shuffle | Mode::Weighted => songs.shuffle(rng),
+            // Songs are popped off the end
+            Mode::Ordered => songs.reverse(),
+            Mode::Spread(gap) => {
+                spread(songs, gap, rng);
+                songs.reverse();
+            }
+            Mode::Album => {
+                let mut albums: BTreeMap<String, Vec<Arc<Track>>> = BTreeMap::new();
+                for song in songs.drain(..) {
+                    albums.entry(album_key(&song)).or_default().push(song);
+                }
+                let mut albums: Vec<_> = albums.into_values().collect();
+                albums.shuffle(rng);
+                for mut album in albums {
+                    album.sort_by(|a, b| {
+                        (a.disc_number, a.track_number, &a.path).cmp(&(
+                            b.disc_number,
+                            b.track_number,
+                            &b.path,
+                        ))
+                    });
+                    songs.extend(album);
+                }
+                songs.reverse();
+            }
+        }
+    }
+    fn pick(
+        self,
+        songs: &mut Vec<Arc<Track>>,
+        stats: &Stats,
+        rng: &mut StdRng,
+    ) -> Option<Arc<Track>> {
+        match self {
+            Mode::Weighted => {
+                let weights = stats.weights(songs);
+                let total: f64 = weights.iter().sum();
+                let index = if total > 0.0 {
+                    let mut choice = rng.random_range(0.0..total);
+                    weights
+                        .iter()
+                        .position(|weight| {
+                            choice -= weight;
+                            choice < 0.0
+                        })
+                        .unwrap_or(weights.len() - 1)
+                } else {
+                    rng.random_range(0..songs.len())
+                };
+                // Every song stays a candidate, with its weight lowered by having just played
+                songs.get(index).cloned()
+            }
+            _ => songs.pop(),
+        }
+    }
+}
+
+/// Songs are grouped by the directory they are in and, if they have one, their album tag, so albums that share a
+/// title, such as greatest hits, stay apart
+fn album_key(track: &Track) -> String {
+    let directory = track
+        .file()
+        .parent()
+        .map(|directory| directory.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match &track.album {
+        Some(album) => format!("{}\0{}", directory, album.to_lowercase()),
+        None => directory,
+    }
+}
+
+fn spread(songs: &mut Vec<Arc<Track>>, gap: usize, rng: &mut StdRng) {
+    fn key(tag: &Option<String>, track: &Track) -> String {
+        match tag {
+            Some(tag) => tag.to_lowercase(),
+            None => track
+                .file()
+                .parent()
+                .map(|directory| directory.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        }
+    }
+    songs.shuffle(rng);
+    // Each artist's songs, still in shuffled order
+    let mut artists: BTreeMap<String, Vec<(String, Arc<Track>)>> = BTreeMap::new();
+    for track in songs.drain(..) {
+        artists
+            .entry(key(&track.artist, &track))
+            .or_default()
+            .push((key(&track.album, &track), track));
+    }
+    let mut buckets: Vec<_> = artists.into_values().collect();
+    // Artists that may go next, weighted by how many songs they have left
+    let mut available = WeightTree::new(buckets.iter().map(Vec::len));
+    let mut by_count: BTreeSet<_> = buckets
+        .iter()
+        .enumerate()
+        .map(|(index, bucket)| (bucket.len(), index))
+        .collect();
+    // How many artists have each number of songs left
+    let mut tied = vec![0; buckets.iter().map(Vec::len).max().unwrap_or(0) + 1];
+    for bucket in &buckets {
+        tied[bucket.len()] += 1;
+    }
+    let total: usize = buckets.iter().map(Vec::len).sum();
+    let mut recent: VecDeque<(usize, String)> = VecDeque::with_capacity(gap + 1);
+    for remaining in (1..=total).rev() {
+        // When the artists with the most songs left only just fit, they have to take turns from now on or there
+        // won't be room to space them all out
+        let urgent = by_count.last().and_then(|&(most, _)| {
+            if (most - 1) * (gap + 1) + tied[most] < remaining {
+                return None;
+            }
+            by_count
+                .iter()
+                .rev()
+                .take_while(|(count, _)| *count == most)
+                .map(|&(_, artist)| artist)
+                .find(|&artist| !is_recent_in(&recent, artist))
+        });
+        // If nothing fits, the gap can't be kept, so pick whoever played least recently
+        let artist = urgent
+            .or_else(|| {
+                (available.total() > 0)
+                    .then(|| available.find(rng.random_range(0..available.total())))
+            })
+            .or_else(|| {
+                recent
+                    .iter()
+                    .map(|(artist, _)| *artist)
+                    .find(|artist| !buckets[*artist].is_empty())
+            })
+            .expect("Songs remain but no artist has any");
+        let bucket = &mut buckets[artist];
+        let index = bucket
+            .iter()
+            .rposition(|(album, _)| !recent.iter().any(|(_, recent)| recent == album))
+            .unwrap_or(bucket.len() - 1);
+        let (album, track) = bucket.remove(index);
+        by_count.remove(&(bucket.len() + 1, artist));
+        tied[bucket.len() + 1] -= 1;
+        tied[bucket.len()] += 1;
+        if !bucket.is_empty() {
+            by_count.insert((bucket.len(), artist));
+        }
+        if gap > 0 {
+            available.set(artist, 0);
+            recent.push_back((artist, album));
+            if recent.len() > gap
+                && let Some((released, _)) = recent.pop_front()
+                && !is_recent_in(&recent, released)
+            {
+                available.set(released, buckets[released].len());
+            }
+        } else {
+            available.set(artist, bucket.len());
+        }
+        songs.push(track);
+    }
+}
+
+/// Whether an artist is among those that played in the last few songs
+fn is_recent_in(recent: &VecDeque<(usize, String)>, artist: usize) -> bool {
+    recent.iter().any(|(recent, _)| *recent == artist)
+}
+
+/// Weights that can be changed and sampled in logarithmic time
+struct WeightTree {
+    weights: Vec<usize>,
+    /// A Fenwick tree of partial sums of the weights
+    sums: Vec<usize>,
+}
+
+impl WeightTree {
+    fn new(weights: impl Iterator<Item = usize>) -> Self {
+        let weights: Vec<_> = weights.collect();
+        let mut tree = WeightTree {
+            sums: vec![0; weights.len() + 1],
+            weights: vec![0; weights.len()],
+        };
+        for (index, weight) in weights.into_iter().enumerate() {
+            tree.set(index, weight);
+        }
+        tree
+    }
+    fn set(&mut self, index: usize, weight: usize) {
+        let previous = std::mem::replace(&mut self.weights[index], weight);
+        let mut position = index + 1;
+        while position < self.sums.len() {
+            self.sums[position] = self.sums[position] + weight - previous;
+            position += position & position.wrapping_neg();
+        }
+    }
+    fn total(&self) -> usize {
+        let mut position = self.weights.len();
+        let mut total = 0;
+        while position > 0 {
+            total += self.sums[position];
+            position -= position & position.wrapping_neg();
+        }
+        total
+    }
+    /// The index where the running total of weights passes this value
+    fn find(&self, mut value: usize) -> usize {
+        let mut position = 0;
+        let mut step = self.sums.len().next_power_of_two();
+        while step > 0 {
+            let next = position + step;
+            if next < self.sums.len() && self.sums[next] <= value {
+                position = next;
+                value -= self.sums[next];
+            }
+            step /= 2;
+        }
+        position
+    }
 }
 
-impl From<SongList> for Playlist {
-    fn from(value: SongList) -> Self {
+impl Playlist {
+    pub fn new(
+        all: SongList,
+        source: Source,
+        mode: Mode,
+        quarantine: Quarantine,
+        stats: Stats,
+        queue: Option<Queue>,
+        seed: Option<u64>,
+    ) -> Self {
         Playlist {
-            all: value,
+            all,
+            source,
+            mode,
+            default_mode: mode,
+            scheduled: None,
+            quarantine,
+            stats,
+            rng: seed.map_or_else(rand::make_rng, StdRng::seed_from_u64),
+            queue,
+            album: None,
+            generation: 0,
+            player: None,
+            restore: None,
             current: Default::default(),
             waiting: None,
         }
     }
 }
 
+impl Playlist {
+    /// Carries on from where this player was, and keeps track of progress from now on
+    pub fn resume(mut self, player: Player) -> Self {
+        self.restore = Some(player.state());
+        self.player = Some(player);
+        self
+    }
+}
+
+fn remaining(current: &[RootSongs]) -> Vec<Vec<Arc<Path>>> {
+    current
+        .iter()
+        .map(|(_, songs, _)| songs.iter().map(|song| song.path.clone()).collect())
+        .collect()
+}
+
+/// Moves on to whatever the schedule says to play now, returning whether that changed
+fn follow_schedule(
+    source: &Source,
+    scheduled: &mut Option<usize>,
+    mode: &mut Mode,
+    default_mode: Mode,
+) -> bool {
+    let Source::Schedule(schedule) = source else {
+        return false;
+    };
+    let active = schedule.active();
+    if active == *scheduled {
+        return false;
+    }
+    *scheduled = active;
+    *mode = active.map_or(default_mode, |index| schedule.entries[index].mode);
+    true
+}
+
 impl Stream for Playlist {
-    type Item = Arc<Path>;
+    type Item = Arc<Track>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let Playlist {
             current,
             all,
+            source,
+            mode,
+            default_mode,
+            scheduled,
+            quarantine,
+            stats,
+            rng,
+            queue,
+            album,
+            generation,
+            player,
+            restore,
             waiting,
         } = self.get_mut();
+        if waiting.is_none()
+            && restore.is_none()
+            && let Some(song) = queue.as_ref().and_then(Queue::pop)
+        {
+            stats.record_play(&song.path);
+            if let Some(player) = player {
+                player.started(&song, Duration::ZERO, remaining(current), *scheduled);
+            }
+            return Poll::Ready(Some(song));
+        }
         loop {
-            if let Some(guard) = waiting.as_mut() {
+            let refilled = if let Some(guard) = waiting.as_mut() {
                 let Poll::Ready(guard) = guard.poll_unpin(cx) else {
                     return Poll::Pending;
                 };
-                current.extend(guard.iter().cloned());
-                current.shuffle(&mut rand::rng());
-            }
-            *waiting = None;
-            if let Some(song) = current.pop() {
-                return Poll::Ready(Some(song));
+                *waiting = None;
+                if let Some(saved) = restore.take() {
+                    // Carry on with the schedule entry the saved order was picked for
+                    if let Source::Schedule(schedule) = &*source
+                        && let Some(entry) = saved
+                            .scheduled
+                            .and_then(|index| schedule.entries.get(index))
+                    {
+                        *scheduled = saved.scheduled;
+                        *mode = entry.mode;
+                    }
+                    current.clear();
+                    for (root, paths) in guard.roots.iter().zip(saved.remaining) {
+                        current.push((
+                            root.root.weight,
+                            paths
+                                .iter()
+                                .filter_map(|path| root.tracks.get(path))
+                                .cloned()
+                                .collect(),
+                            None,
+                        ));
+                    }
+                    // The saved order already reflects this library
+                    *generation = guard.generation;
+                    if let Some(song) = saved.current.and_then(|path| guard.get(&path).cloned())
+                        && !quarantine.is_quarantined(&song.path)
+                    {
+                        if let Some(player) = player {
+                            player.started(&song, saved.position, remaining(current), *scheduled);
+                        }
+                        return Poll::Ready(Some(Arc::new(song.starting_at(saved.position))));
+                    }
+                }
+                let rescheduled = follow_schedule(source, scheduled, mode, *default_mode);
+                // A schedule plays whatever its active entry names, or everything when none is active
+                let target = match &*source {
+                    Source::Schedule(schedule) => {
+                        scheduled.map(|index| &schedule.entries[index].source)
+                    }
+                    source => Some(source),
+                };
+                let mut filter = match target {
+                    Some(Source::Filter(filter)) => Some(filter.clone()),
+                    Some(Source::Query(queries, name)) => {
+                        let filter = queries.get(name);
+                        if filter.is_none() {
+                            eprintln!("No saved query {}, playing everything instead", name);
+                        }
+                        filter
+                    }
+                    _ => None,
+                };
+                let mut from_file = false;
+                if let Some(Source::PlaylistFile(name)) = target {
+                    current.resize_with(1, Default::default);
+                    let (weight, songs, _) = &mut current[0];
+                    *weight = 1;
+                    let stale =
+                        rescheduled || (*mode == Mode::Weighted && *generation != guard.generation);
+                    *generation = guard.generation;
+                    if songs.is_empty() || stale {
+                        songs.clear();
+                        if let Some(playlist) = guard.playlist(name) {
+                            songs.extend(
+                                playlist
+                                    .entries
+                                    .iter()
+                                    .filter_map(|entry| guard.get(entry))
+                                    .filter(|track| !quarantine.is_quarantined(&track.path))
+                                    .cloned(),
+                            );
+                        }
+                        mode.arrange(songs, rng);
+                    }
+                    from_file = !songs.is_empty();
+                    if !from_file {
+                        if !matches!(source, Source::Schedule(_)) {
+                            eprintln!("Playlist {} has nothing playable", name);
+                            return Poll::Ready(None);
+                        }
+                        eprintln!(
+                            "Playlist {} has nothing playable, playing everything instead",
+                            name
+                        );
+                    }
+                }
+                if !from_file {
+                    // A filter's matches and weighted candidates change with the library, so start afresh when it
+                    // does
+                    let mut stale = rescheduled
+                        || ((filter.is_some() || *mode == Mode::Weighted)
+                            && *generation != guard.generation);
+                    *generation = guard.generation;
+                    current.resize_with(guard.roots.len(), Default::default);
+                    loop {
+                        for ((weight, songs, empty_at), root) in
+                            current.iter_mut().zip(&guard.roots)
+                        {
+                            *weight = root.root.weight;
+                            if stale || (songs.is_empty() && *empty_at != Some(*generation)) {
+                                songs.clear();
+                                songs.extend(
+                                    root.tracks
+                                        .values()
+                                        .filter(|track| {
+                                            filter.as_ref().is_none_or(|filter| {
+                                                filter.matches(&root.root.path, track)
+                                            }) && !quarantine.is_quarantined(&track.path)
+                                                && !guard.is_redundant(track, quarantine)
+                                        })
+                                        .cloned(),
+                                );
+                                mode.arrange(songs, rng);
+                                *empty_at = songs.is_empty().then_some(*generation);
+                            }
+                        }
+                        match &filter {
+                            Some(unmatched)
+                                if current.iter().all(|(_, songs, _)| songs.is_empty()) =>
+                            {
+                                if !matches!(source, Source::Schedule(_)) {
+                                    eprintln!("Nothing playable matches {}", unmatched);
+                                    return Poll::Ready(None);
+                                }
+                                // A scheduled player has to keep playing something
+                                eprintln!(
+                                    "Nothing playable matches {}, playing everything instead",
+                                    unmatched
+                                );
+                                filter = None;
+                                stale = true;
+                            }
+                            _ => break,
+                        }
+                    }
+                }
+                true
+            } else {
+                false
+            };
+            let library_changed = || {
+                all.try_read()
+                    .is_ok_and(|library| library.generation != *generation)
+            };
+            // Switch between scheduled entries at the end of a song
+            let changed = match &*source {
+                Source::Filter(_) => library_changed(),
+                Source::Schedule(schedule) => schedule.active() != *scheduled || library_changed(),
+                _ => *mode == Mode::Weighted && library_changed(),
+            };
+            // Each root keeps its own cycle, so a small root repeats sooner rather than being drowned out
+            // A root that had nothing to play is only searched again once the library changes
+            let exhausted = current.iter().any(|(_, songs, empty_at)| {
+                songs.is_empty() && (*empty_at != Some(*generation) || library_changed())
+            });
+            if refilled || (!changed && !exhausted) {
+                let total: u32 = current
+                    .iter()
+                    .filter(|(_, songs, _)| !songs.is_empty())
+                    .map(|(weight, _, _)| *weight)
+                    .sum();
+                if total > 0 {
+                    // Stay with the root that is part way through an album
+                    let sticky = album.as_ref().and_then(|(index, key)| {
+                        let (_, songs, _) = current.get(*index)?;
+                        (album_key(songs.last()?) == *key).then_some(*index)
+                    });
+                    let index = sticky.or_else(|| {
+                        let mut choice = rng.random_range(0..total);
+                        current.iter().position(|(weight, songs, _)| {
+                            if songs.is_empty() {
+                                return false;
+                            }
+                            if choice < *weight {
+                                return true;
+                            }
+                            choice -= *weight;
+                            false
+                        })
+                    });
+                    let song = index.and_then(|index| {
+                        let song = mode.pick(&mut current[index].1, stats, rng)?;
+                        if *mode == Mode::Album {
+                            *album = Some((index, album_key(&song)));
+                        }
+                        Some(song)
+                    });
+                    match song {
+                        Some(song) if quarantine.is_quarantined(&song.path) => {
+                            // Weighted picks leave songs in place, so take it out for good
+                            if let Some(index) = index {
+                                current[index].1.retain(|other| !Arc::ptr_eq(other, &song));
+                            }
+                            continue;
+                        }
+                        Some(song) => {
+                            stats.record_play(&song.path);
+                            if let Some(player) = player {
+                                player.started(
+                                    &song,
+                                    Duration::ZERO,
+                                    remaining(current),
+                                    *scheduled,
+                                );
+                            }
+                            return Poll::Ready(Some(song));
+                        }
+                        None => {}
+                    }
+                }
+                if refilled {
+                    // Going around again would only find the same nothing
+                    eprintln!("Nothing left to play");
+                    return Poll::Ready(None);
+                }
             }
             *waiting = Some(all.clone().read_owned().boxed());
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Songs by artists numbered from zero, with this many songs each, every song on its own album
+    fn songs(counts: &[usize]) -> Vec<Arc<Track>> {
+        let mut songs = Vec::new();
+        for (artist, &count) in counts.iter().enumerate() {
+            for number in 0..count {
+                let path: Arc<Path> =
+                    Path::new(&format!("/music/{}/{}.flac", artist, number)).into();
+                songs.push(Arc::new(Track {
+                    path,
+                    size: 0,
+                    modified: None,
+                    title: None,
+                    artist: Some(artist.to_string()),
+                    album: Some(format!("{} {}", artist, number)),
+                    genre: None,
+                    track_number: None,
+                    disc_number: None,
+                    year: None,
+                    duration: None,
+                    codec: None,
+                    sample_rate: None,
+                    span: None,
+                }));
+            }
+        }
+        songs
+    }
+
+    /// Whether the artists with the most songs can take turns and still leave the gap between each
+    fn fits(counts: &[usize], gap: usize) -> bool {
+        let most = counts.iter().copied().max().unwrap_or(0);
+        let tied = counts.iter().filter(|&&count| count == most).count();
+        most == 0 || (most - 1) * (gap + 1) + tied <= counts.iter().sum()
+    }
+
+    fn assert_spread(counts: &[usize], gap: usize, seed: u64) {
+        let mut spread = songs(counts);
+        Mode::Spread(gap).arrange(&mut spread, &mut StdRng::seed_from_u64(seed));
+        assert_eq!(spread.len(), counts.iter().sum::<usize>());
+        let artists: Vec<_> = spread.iter().map(|song| song.artist.clone()).collect();
+        for window in artists.windows(gap + 1) {
+            let distinct: BTreeSet<_> = window.iter().collect();
+            assert_eq!(
+                distinct.len(),
+                window.len(),
+                "Gap {} broken for {:?} with seed {}: {:?}",
+                gap,
+                counts,
+                seed,
+                artists
+            );
+        }
+    }
+
+    #[test]
+    fn spread_keeps_gap_when_tied() {
+        for seed in 0..1000 {
+            assert_spread(&[3, 3, 2], 2, seed);
+        }
+    }
+
+    #[test]
+    fn spread_keeps_gap() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for seed in 0..2000 {
+            let gap = rng.random_range(0..5);
+            let artists = rng.random_range(1..8);
+            let counts: Vec<usize> = (0..artists).map(|_| rng.random_range(1..7)).collect();
+            if fits(&counts, gap) {
+                assert_spread(&counts, gap, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn spread_keeps_every_song_when_gap_cannot_be_kept() {
+        let mut spread = songs(&[5, 1]);
+        Mode::Spread(2).arrange(&mut spread, &mut StdRng::seed_from_u64(0));
+        let paths: BTreeSet<_> = spread.iter().map(|song| song.path.clone()).collect();
+        assert_eq!(
+            paths,
+            songs(&[5, 1])
+                .iter()
+                .map(|song| song.path.clone())
+                .collect()
+        );
+    }
+
+    #[test]
+    fn weight_tree_totals() {
+        let mut tree = WeightTree::new([3, 0, 5, 2].into_iter());
+        assert_eq!(tree.total(), 10);
+        tree.set(2, 1);
+        assert_eq!(tree.total(), 6);
+        tree.set(1, 4);
+        assert_eq!(tree.total(), 10);
+        assert_eq!(WeightTree::new(std::iter::empty()).total(), 0);
+    }
+
+    #[test]
+    fn weight_tree_finds() {
+        let mut tree = WeightTree::new([3, 0, 5, 2, 1].into_iter());
+        let found: Vec<_> = (0..tree.total()).map(|value| tree.find(value)).collect();
+        assert_eq!(found, [0, 0, 0, 2, 2, 2, 2, 2, 3, 3, 4]);
+        tree.set(0, 0);
+        tree.set(1, 2);
+        let found: Vec<_> = (0..tree.total()).map(|value| tree.find(value)).collect();
+        assert_eq!(found, [1, 1, 2, 2, 2, 2, 2, 3, 3, 4]);
+    }
+}
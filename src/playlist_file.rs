@@ -0,0 +1,185 @@
+use std::path::{Component, Path, PathBuf};
+
+const EXTENSIONS: &[&str] = &["m3u", "m3u8", "pls"];
+
+#[derive(Debug)]
+pub struct PlaylistFile {
+    pub name: String,
+    pub path: PathBuf,
+    pub entries: Vec<PathBuf>,
+}
+
+impl PlaylistFile {
+    pub fn is_playlist(path: &Path) -> bool {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|extension| EXTENSIONS.iter().any(|e| e.eq_ignore_ascii_case(extension)))
+    }
+    pub fn read(root: &Path, path: &Path) -> std::io::Result<Self> {
+        let contents = std::fs::read(path)?;
+        let contents = String::from_utf8_lossy(&contents);
+        let directory = path.parent().unwrap_or(root);
+        let is_pls = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("pls"));
+        let entries = if is_pls {
+            parse_pls(&contents)
+        } else {
+            parse_m3u(&contents)
+        }
+        .into_iter()
+        .filter_map(|entry| resolve(directory, entry))
+        .collect();
+        let name = path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .with_extension("")
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        Ok(PlaylistFile {
+            name,
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+}
+
+fn parse_m3u(contents: &str) -> Vec<&str> {
+    contents
+        .lines()
+        .map(|line| line.trim().trim_start_matches('\u{feff}'))
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+fn parse_pls(contents: &str) -> Vec<&str> {
+    let mut entries: Vec<(u32, &str)> = contents
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once('=')?;
+            let index = key.trim().strip_prefix("File")?.parse().ok()?;
+            Some((index, value.trim()))
+        })
+        .collect();
+    entries.sort_by_key(|(index, _)| *index);
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
+fn resolve(directory: &Path, entry: &str) -> Option<PathBuf> {
+    let entry = match entry.strip_prefix("file://") {
+        Some(url) => percent_encoding::percent_decode_str(url)
+            .decode_utf8_lossy()
+            .into_owned(),
+        None if entry.contains("://") => return None,
+        None => entry.replace('\\', "/"),
+    };
+    Some(normalize(&directory.join(entry)))
+}
+
+/// Collapses "." and ".." lexically, since library paths are never canonicalised
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::CurDir => {}
+            component => resolved.push(component),
+        }
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a playlist under a root of its own and reads it back
+    fn read(test: &str, name: &str, contents: &str) -> (PathBuf, PlaylistFile) {
+        let root =
+            std::env::temp_dir().join(format!("radio-music-box-{}-{}", std::process::id(), test));
+        let path = root.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        let playlist = PlaylistFile::read(&root, &path).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        (root, playlist)
+    }
+
+    #[test]
+    fn reads_m3u_entries() {
+        let (root, playlist) = read(
+            "m3u",
+            "Mixes/Road Trip.m3u8",
+            "\u{feff}#EXTM3U\r\n\
+             #EXTINF:200,Miles Davis - So What\r\n\
+             ../Jazz/so what.flac\r\n\
+             \r\n\
+             \x20 ./one more time.mp3 \r\n\
+             Pop\\Daft Punk\\around the world.mp3\r\n\
+             /music/absolute.ogg\r\n\
+             file:///music/with%20spaces.ogg\r\n\
+             http://radio.example/stream\r\n",
+        );
+        assert_eq!(playlist.name, "Mixes/Road Trip");
+        assert_eq!(playlist.path, root.join("Mixes/Road Trip.m3u8"));
+        assert_eq!(
+            playlist.entries,
+            [
+                root.join("Jazz/so what.flac"),
+                root.join("Mixes/one more time.mp3"),
+                root.join("Mixes/Pop/Daft Punk/around the world.mp3"),
+                PathBuf::from("/music/absolute.ogg"),
+                PathBuf::from("/music/with spaces.ogg"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_pls_entries_in_order() {
+        let (root, playlist) = read(
+            "pls",
+            "Favourites.PLS",
+            "[playlist]\n\
+             File2=second.mp3\n\
+             Title2=Second\n\
+             File10 = tenth.mp3\n\
+             File1=../first.mp3\n\
+             Length1=200\n\
+             Filename=ignored.mp3\n\
+             NumberOfEntries=3\n",
+        );
+        assert_eq!(playlist.name, "Favourites");
+        assert_eq!(
+            playlist.entries,
+            [
+                root.parent().unwrap().join("first.mp3"),
+                root.join("second.mp3"),
+                root.join("tenth.mp3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn recognises_playlists() {
+        assert!(PlaylistFile::is_playlist(Path::new("a/list.m3u")));
+        assert!(PlaylistFile::is_playlist(Path::new("a/list.M3U8")));
+        assert!(PlaylistFile::is_playlist(Path::new("a/list.pls")));
+        assert!(!PlaylistFile::is_playlist(Path::new("a/album.cue")));
+        assert!(!PlaylistFile::is_playlist(Path::new("a/m3u")));
+    }
+
+    #[test]
+    fn normalizes_lexically() {
+        assert_eq!(
+            normalize(Path::new("/music/./a/../b/../../c.mp3")),
+            Path::new("/c.mp3")
+        );
+        assert_eq!(normalize(Path::new("/../c.mp3")), Path::new("/c.mp3"));
+        assert_eq!(normalize(Path::new("a/./b")), Path::new("a/b"));
+    }
+}
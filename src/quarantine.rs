@@ -0,0 +1,99 @@
+use crate::state;
+use crate::state::{ByPath, Persisted};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Failure {
+    pub path: Arc<Path>,
+    pub failures: u32,
+    pub error: String,
+    pub last_failure: u64,
+    #[serde(skip_deserializing)]
+    pub quarantined: bool,
+}
+
+#[derive(Clone)]
+pub struct Quarantine {
+    limit: u32,
+    failures: Persisted<ByPath<Failure>>,
+}
+
+impl Quarantine {
+    pub fn new(limit: u32, path: Option<PathBuf>) -> Self {
+        let failures = path
+            .as_deref()
+            .and_then(state::read::<Vec<Failure>>)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|failure| (failure.path.clone(), failure))
+            .collect();
+        Quarantine {
+            limit,
+            failures: Persisted::new(path, ByPath(failures)),
+        }
+    }
+    pub fn is_quarantined(&self, song: &Path) -> bool {
+        self.limit > 0
+            && self
+                .failures
+                .lock()
+                .get(song)
+                .is_some_and(|failure| failure.failures >= self.limit)
+    }
+    pub fn record_failure(&self, song: &Arc<Path>, error: impl Display) {
+        eprintln!("{}: {}", song.display(), error);
+        let mut failures = self.failures.lock();
+        let failure = failures.entry(song.clone()).or_insert_with(|| Failure {
+            path: song.clone(),
+            failures: 0,
+            error: String::new(),
+            last_failure: 0,
+            quarantined: false,
+        });
+        failure.failures += 1;
+        failure.error = error.to_string();
+        failure.last_failure = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if self.limit > 0 && failure.failures == self.limit {
+            eprintln!(
+                "Quarantining {} after {} failures",
+                song.display(),
+                failure.failures
+            );
+        }
+        self.failures.changed();
+    }
+    pub fn record_success(&self, song: &Path) {
+        let mut failures = self.failures.lock();
+        if failures.remove(song).is_some() {
+            self.failures.changed();
+        }
+    }
+    pub fn clear(&self, song: &Path) -> bool {
+        let mut failures = self.failures.lock();
+        let removed = failures.remove(song).is_some();
+        if removed {
+            self.failures.changed();
+        }
+        removed
+    }
+    pub fn failures(&self) -> Vec<Failure> {
+        self.failures
+            .lock()
+            .values()
+            .map(|failure| Failure {
+                quarantined: self.limit > 0 && failure.failures >= self.limit,
+                ..failure.clone()
+            })
+            .collect()
+    }
+    pub fn save(&self) {
+        self.failures.save();
+    }
+}
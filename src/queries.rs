@@ -0,0 +1,74 @@
+use crate::filter::Filter;
+use crate::state::{self, Persisted};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, MutexGuard};
+
+#[derive(Deserialize, Serialize)]
+struct SavedQuery {
+    name: String,
+    query: String,
+}
+
+/// Queries by name, saved as a list of their names and text
+struct SavedQueries(BTreeMap<String, Arc<Filter>>);
+
+impl Serialize for SavedQueries {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().map(|(name, filter)| SavedQuery {
+            name: name.clone(),
+            query: filter.to_string(),
+        }))
+    }
+}
+
+/// Named queries that can be saved and streamed over HTTP
+#[derive(Clone)]
+pub struct Queries(Persisted<SavedQueries>);
+
+impl Queries {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let queries = path
+            .as_deref()
+            .and_then(state::read::<Vec<SavedQuery>>)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|saved| match saved.query.parse() {
+                Ok(filter) => Some((saved.name, Arc::new(filter))),
+                Err(e) => {
+                    eprintln!("Ignoring saved query {}: {}", saved.name, e);
+                    None
+                }
+            })
+            .collect();
+        Queries(Persisted::new(path, SavedQueries(queries)))
+    }
+    pub fn get(&self, name: &str) -> Option<Arc<Filter>> {
+        self.lock().0.get(name).cloned()
+    }
+    pub fn all(&self) -> Vec<(String, Arc<Filter>)> {
+        self.lock()
+            .0
+            .iter()
+            .map(|(name, filter)| (name.clone(), filter.clone()))
+            .collect()
+    }
+    pub fn save(&self, name: String, filter: Filter) {
+        self.lock().0.insert(name, Arc::new(filter));
+        self.0.changed();
+    }
+    pub fn remove(&self, name: &str) -> bool {
+        let removed = self.lock().0.remove(name).is_some();
+        if removed {
+            self.0.changed();
+        }
+        removed
+    }
+    pub fn flush(&self) {
+        self.0.save();
+    }
+    fn lock(&self) -> MutexGuard<'_, SavedQueries> {
+        self.0.lock()
+    }
+}
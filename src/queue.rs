@@ -0,0 +1,72 @@
+use crate::track::Track;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Serialize)]
+pub struct QueueEntry {
+    /// Identifies the entry while it moves around the queue
+    pub id: u64,
+    pub track: Arc<Track>,
+}
+
+/// Songs requested to play next, ahead of whatever the playlist would pick
+#[derive(Clone, Default)]
+pub struct Queue(Arc<Mutex<QueueState>>);
+
+#[derive(Default)]
+struct QueueState {
+    next_id: u64,
+    entries: VecDeque<QueueEntry>,
+}
+
+impl Queue {
+    pub fn entries(&self) -> Vec<QueueEntry> {
+        self.lock().entries.iter().cloned().collect()
+    }
+    /// Adds songs at a position in the queue, or the end if not given
+    pub fn add(&self, tracks: Vec<Arc<Track>>, position: Option<usize>) -> Vec<QueueEntry> {
+        let mut state = self.lock();
+        let position = position.map_or(state.entries.len(), |position| {
+            position.min(state.entries.len())
+        });
+        let first_id = state.next_id;
+        state.next_id += tracks.len() as u64;
+        let added: Vec<_> = tracks
+            .into_iter()
+            .zip(first_id..)
+            .map(|(track, id)| QueueEntry { id, track })
+            .collect();
+        for (offset, entry) in added.iter().enumerate() {
+            state.entries.insert(position + offset, entry.clone());
+        }
+        added
+    }
+    pub fn move_to(&self, id: u64, position: usize) -> bool {
+        let mut state = self.lock();
+        match state.entries.iter().position(|entry| entry.id == id) {
+            Some(index) => {
+                let entry = state.entries.remove(index).expect("Entry went missing");
+                let position = position.min(state.entries.len());
+                state.entries.insert(position, entry);
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn remove(&self, id: u64) -> bool {
+        let mut state = self.lock();
+        let count = state.entries.len();
+        state.entries.retain(|entry| entry.id != id);
+        state.entries.len() != count
+    }
+    pub fn clear(&self) {
+        self.lock().entries.clear();
+    }
+    pub fn pop(&self) -> Option<Arc<Track>> {
+        self.lock().entries.pop_front().map(|entry| entry.track)
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.0.lock().expect("Failed to unlock queue")
+    }
+}
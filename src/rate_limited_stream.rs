@@ -57,10 +57,10 @@ where
             last_checked,
             sleep,
         } = self.get_mut();
-        if let Some(sleep) = sleep.as_mut() {
-            if let Poll::Pending = sleep.poll_unpin(cx) {
-                return Poll::Pending;
-            }
+        if let Some(sleep) = sleep.as_mut()
+            && sleep.poll_unpin(cx).is_pending()
+        {
+            return Poll::Pending;
         }
         *sleep = None;
         match Pin::new(stream).poll_next(cx) {
@@ -81,8 +81,7 @@ where
                     // Wake up a bit early to give processing time
                     let duration = u64::try_from(-*tokens / S::Item::RATE as i128 * 1000)
                         .unwrap_or_default()
-                        .checked_sub(100)
-                        .unwrap_or_default();
+                        .saturating_sub(100);
                     let mut s = Box::pin(tokio::time::sleep(Duration::from_millis(duration)));
                     let _ = s.poll_unpin(cx);
                     *sleep = Some(s);
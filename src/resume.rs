@@ -0,0 +1,94 @@
+use crate::state;
+use crate::state::Persisted;
+use crate::track::Track;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The position in a song is saved each time it moves on this far
+const POSITION_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Where a player had got to, so it can carry on after a restart
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct PlayerState {
+    pub current: Option<Arc<Path>>,
+    pub position: Duration,
+    /// The songs left to play from each music root, last first
+    pub remaining: Vec<Vec<Arc<Path>>>,
+    /// The schedule entry the remaining songs were picked for
+    pub scheduled: Option<usize>,
+    pub paused: bool,
+}
+
+#[derive(Clone)]
+pub struct Resume {
+    players: Persisted<BTreeMap<String, PlayerState>>,
+}
+
+/// The saved state of one player
+#[derive(Clone)]
+pub struct Player {
+    resume: Resume,
+    name: &'static str,
+}
+
+impl Resume {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let players = path.as_deref().and_then(state::read).unwrap_or_default();
+        Resume {
+            players: Persisted::new(path, players),
+        }
+    }
+    pub fn player(&self, name: &'static str) -> Player {
+        Player {
+            resume: self.clone(),
+            name,
+        }
+    }
+    pub fn save(&self) {
+        self.players.save();
+    }
+}
+
+impl Player {
+    pub fn state(&self) -> PlayerState {
+        self.resume
+            .players
+            .lock()
+            .get(self.name)
+            .cloned()
+            .unwrap_or_default()
+    }
+    pub fn started(
+        &self,
+        track: &Track,
+        position: Duration,
+        remaining: Vec<Vec<Arc<Path>>>,
+        scheduled: Option<usize>,
+    ) {
+        let mut players = self.resume.players.lock();
+        let state = players.entry(self.name.to_string()).or_default();
+        state.current = Some(track.path.clone());
+        state.position = position;
+        state.remaining = remaining;
+        state.scheduled = scheduled;
+        self.resume.players.changed();
+    }
+    /// Moves the position in the current song on by this many frames of audio
+    pub fn played(&self, frames: usize, sample_rate: u32) {
+        if let Some(state) = self.resume.players.lock().get_mut(self.name) {
+            let before = state.position.as_secs() / POSITION_INTERVAL.as_secs();
+            state.position += Duration::from_secs_f64(frames as f64 / sample_rate as f64);
+            if state.position.as_secs() / POSITION_INTERVAL.as_secs() != before {
+                self.resume.players.changed();
+            }
+        }
+    }
+    pub fn set_paused(&self, paused: bool) {
+        let mut players = self.resume.players.lock();
+        players.entry(self.name.to_string()).or_default().paused = paused;
+        self.resume.players.changed();
+    }
+}
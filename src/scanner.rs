@@ -1,128 +1,882 @@
 use crate::SongList;
+use crate::archive;
+use crate::cache;
+use crate::cue_sheet::CueSheet;
+use crate::exclusions::Exclusions;
+use crate::library::{CueSheets, Library, LibraryRoot, Playlists, Root, Tracks, Unplayable};
+use crate::playlist_file::PlaylistFile;
+use crate::track::{Span, Track};
+use async_watcher::notify::event::{ModifyKind, RenameMode};
 use async_watcher::notify::{Error, ErrorKind, EventKind, RecursiveMode};
 use async_watcher::{AsyncDebouncer, DebouncedEvent};
 use std::collections::BTreeSet;
+use std::fs::Metadata;
+use std::ops::Bound;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use tokio::signal::unix::SignalKind;
 use tokio::sync::{RwLock, broadcast};
+use tokio::task::JoinHandle;
 use walkdir::WalkDir;
 
-fn scan(path: &Path) -> BTreeSet<Arc<Path>> {
-    let result: BTreeSet<Arc<Path>> = WalkDir::new(path)
+const NOT_AUDIO_EXTENSIONS: &[&str] = &["cue", "db", "ini", "log", "md5", "nfo", "sfv"];
+
+fn might_be_audio(path: &Path) -> bool {
+    if path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|extension| {
+            NOT_AUDIO_EXTENSIONS
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case(extension))
+        })
+    {
+        return false;
+    }
+    !mime_guess::from_path(path).iter().any(|mime| {
+        mime.type_() == mime_guess::mime::IMAGE || mime.type_() == mime_guess::mime::TEXT
+    })
+}
+
+fn scan(
+    root_path: &Path,
+    path: &Path,
+    exclusions: &Exclusions,
+    cache: &Tracks,
+    unplayable_cache: &Unplayable,
+) -> (Tracks, Playlists, CueSheets, Unplayable) {
+    let mut probed = 0;
+    let mut skipped = 0;
+    let mut playlists = Playlists::new();
+    let mut sheets = CueSheets::new();
+    let mut unplayable = Unplayable::new();
+    let mut files = Vec::new();
+    let mut result = Tracks::new();
+    exclusions.reload();
+    for entry in WalkDir::new(path)
         .follow_links(true)
         .into_iter()
-        .filter_map(|entry| match entry {
+        .filter_entry(|entry| !exclusions.is_excluded(entry.path(), entry.file_type().is_dir()))
+    {
+        let entry = match entry {
+            Ok(entry) => entry,
             Err(e) => {
                 eprintln!("Failed searching files: {}", e);
-                None
+                continue;
             }
-            Ok(entry) => {
-                if entry.file_type().is_file() {
-                    Some(entry.into_path().into())
-                } else {
-                    None
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", entry.path().display(), e);
+                continue;
+            }
+        };
+        let path: Arc<Path> = entry.into_path().into();
+        if PlaylistFile::is_playlist(&path) {
+            if let Some(playlist) = read_playlist(root_path, &path) {
+                playlists.insert(path, Arc::new(playlist));
+            }
+        } else if CueSheet::is_cue_sheet(&path) {
+            for track in read_cue_sheet(&path, exclusions, cache, &mut sheets, &mut probed) {
+                result.insert(track.path.clone(), track);
+            }
+        } else if archive::is_archive(&path) {
+            let tracks = read_archive(
+                &path,
+                &metadata,
+                cache,
+                unplayable_cache,
+                &mut unplayable,
+                &mut probed,
+                &mut skipped,
+            );
+            result.extend(tracks);
+        } else {
+            files.push((path, metadata));
+        }
+    }
+    // Files split up by a CUE sheet are only played as their virtual tracks
+    let covered: BTreeSet<_> = result.values().map(|track| track.file().clone()).collect();
+    for (path, metadata) in files {
+        if covered.contains(&path) {
+            continue;
+        }
+        let stamp = stamp(&metadata);
+        let track = match cache.get(&path) {
+            Some(track) if track.is_unchanged(&metadata) => Some(track.clone()),
+            _ if unplayable_cache.get(&path) == Some(&stamp) => None,
+            _ => {
+                probed += 1;
+                probe(path.clone(), &metadata).map(Arc::new)
+            }
+        };
+        match track {
+            Some(track) => {
+                result.insert(path, track);
+            }
+            None => {
+                unplayable.insert(path, stamp);
+                skipped += 1;
+            }
+        }
+    }
+    eprintln!(
+        "Scanned {} files and {} playlists ({} probed, {} skipped as not playable)",
+        result.len(),
+        playlists.len(),
+        probed,
+        skipped
+    );
+    (result, playlists, sheets, unplayable)
+}
+
+fn rescan(root: &mut LibraryRoot, exclusions: &Exclusions) {
+    let (tracks, playlists, sheets, unplayable) = scan(
+        &root.root.path,
+        &root.root.path,
+        exclusions,
+        &root.tracks,
+        &root.unplayable,
+    );
+    root.tracks = tracks;
+    root.playlists = playlists;
+    root.cue_sheets = sheets;
+    root.unplayable = unplayable;
+}
+
+/// What identifies a version of a file
+fn stamp(metadata: &Metadata) -> (u64, Option<SystemTime>) {
+    (metadata.len(), metadata.modified().ok())
+}
+
+fn read_playlist(root_path: &Path, path: &Path) -> Option<PlaylistFile> {
+    match PlaylistFile::read(root_path, path) {
+        Ok(playlist) => Some(playlist),
+        Err(e) => {
+            eprintln!("Failed to read playlist {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn read_archive(
+    path: &Path,
+    metadata: &Metadata,
+    cache: &Tracks,
+    unplayable_cache: &Unplayable,
+    unplayable: &mut Unplayable,
+    probed: &mut usize,
+    skipped: &mut usize,
+) -> Vec<(Arc<Path>, Arc<Track>)> {
+    let members = match archive::list(path) {
+        Ok(members) => members,
+        Err(e) => {
+            eprintln!("Skipping archive {}: {}", path.display(), e);
+            return Vec::new();
+        }
+    };
+    members
+        .into_iter()
+        .filter(|member| might_be_audio(&member.path))
+        .filter_map(|member| {
+            let size = member.size();
+            let path: Arc<Path> = member.path.into();
+            // Members are only as fresh as the archive holding them
+            let stamp = (size, metadata.modified().ok());
+            let track = match cache.get(&path) {
+                Some(track) if (track.size, track.modified) == stamp => Some(track.clone()),
+                _ if unplayable_cache.get(&path) == Some(&stamp) => None,
+                _ => {
+                    *probed += 1;
+                    probe(path.clone(), metadata).map(|track| Arc::new(Track { size, ..track }))
                 }
+            };
+            if track.is_none() {
+                unplayable.insert(path.clone(), stamp);
+                *skipped += 1;
             }
+            Some((path, track?))
         })
-        .collect();
-    eprintln!("Scanned {} files", result.len());
+        .collect()
+}
+
+/// Reads the tracks in a CUE sheet, noting which files it names
+fn read_cue_sheet(
+    path: &Path,
+    exclusions: &Exclusions,
+    cache: &Tracks,
+    sheets: &mut CueSheets,
+    probed: &mut usize,
+) -> Vec<Arc<Track>> {
+    let sheet = match CueSheet::read(path) {
+        Ok(sheet) => sheet,
+        Err(e) => {
+            eprintln!("Failed to read CUE sheet {}: {}", path.display(), e);
+            return Vec::new();
+        }
+    };
+    let mut result = Vec::new();
+    for file in sheet.files {
+        // A sheet can only split files the library would play by themselves
+        if !exclusions.is_inside(&file.path) || exclusions.is_excluded(&file.path, false) {
+            eprintln!(
+                "Skipping {} from {}, which the library leaves out",
+                file.path.display(),
+                path.display()
+            );
+            continue;
+        }
+        sheets
+            .entry(file.path.as_path().into())
+            .or_default()
+            .insert(path.into());
+        let metadata = match std::fs::metadata(&file.path) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                eprintln!(
+                    "Failed to read {} from {}: {}",
+                    file.path.display(),
+                    path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+        let file_path: Arc<Path> = file.path.into();
+        let mut whole = None;
+        for (index, cue_track) in file.tracks.iter().enumerate() {
+            let span = Span {
+                file: file_path.clone(),
+                start: cue_track.start,
+                end: file.tracks.get(index + 1).map(|next| next.start),
+            };
+            let track_path: Arc<Path> = path.join(format!("{:02}", cue_track.number)).into();
+            let mut track = match cache.get(&track_path) {
+                Some(track)
+                    if track.span.as_ref() == Some(&span) && track.is_unchanged(&metadata) =>
+                {
+                    Track::clone(track)
+                }
+                _ => {
+                    let whole = whole.get_or_insert_with(|| {
+                        *probed += 1;
+                        probe(file_path.clone(), &metadata)
+                    });
+                    let Some(whole) = whole else {
+                        break;
+                    };
+                    whole.slice(track_path, span)
+                }
+            };
+            track.title = cue_track.title.clone();
+            track.artist = cue_track
+                .performer
+                .clone()
+                .or_else(|| sheet.performer.clone())
+                .or(track.artist);
+            track.album = sheet.title.clone().or(track.album);
+            track.track_number = Some(cue_track.number);
+            result.push(Arc::new(track));
+        }
+    }
     result
 }
 
+fn update_cue_sheet(root: &mut LibraryRoot, path: &Path, exclusions: &Exclusions) -> bool {
+    let previous = remove_virtual_tracks(root, path);
+    forget_cue_sheets(root, path);
+    let mut probed = 0;
+    let tracks = if path.exists() && !exclusions.is_excluded(path, false) {
+        read_cue_sheet(
+            path,
+            exclusions,
+            &root.tracks,
+            &mut root.cue_sheets,
+            &mut probed,
+        )
+    } else {
+        Vec::new()
+    };
+    let covered: BTreeSet<_> = tracks.iter().map(|track| track.file().clone()).collect();
+    let changed = !previous.is_empty() || !tracks.is_empty();
+    for track in tracks {
+        root.tracks.insert(track.path.clone(), track);
+    }
+    for file in &covered {
+        root.tracks.remove(file);
+    }
+    // Anything the sheet used to cover goes back to being an ordinary track
+    for file in previous.difference(&covered) {
+        if let Ok(metadata) = std::fs::metadata(file)
+            && !exclusions.is_excluded(file, false)
+        {
+            update_file(root, file, &metadata);
+        }
+    }
+    changed
+}
+
+fn remove_virtual_tracks(root: &mut LibraryRoot, path: &Path) -> BTreeSet<Arc<Path>> {
+    let doomed: Vec<_> = root
+        .tracks
+        .range::<Path, _>((Bound::Excluded(path), Bound::Unbounded))
+        .map(|(_, track)| track)
+        .take_while(|track| track.path.starts_with(path))
+        .filter(|track| track.span.is_some())
+        .cloned()
+        .collect();
+    doomed
+        .into_iter()
+        .map(|track| {
+            root.tracks.remove(&track.path);
+            track.file().clone()
+        })
+        .collect()
+}
+
+/// Drops what is known about the files named by CUE sheets at or under a path
+fn forget_cue_sheets(root: &mut LibraryRoot, path: &Path) {
+    root.cue_sheets.retain(|_, sheets| {
+        sheets.retain(|sheet| !sheet.starts_with(path));
+        !sheets.is_empty()
+    });
+}
+
+fn probe(path: Arc<Path>, metadata: &Metadata) -> Option<Track> {
+    if !might_be_audio(&path) {
+        return None;
+    }
+    match Track::probe(path.clone(), metadata) {
+        Ok(track) => Some(track),
+        Err(e) => {
+            eprintln!("Skipping {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn update(root: &mut LibraryRoot, path: &Path, exclusions: &Exclusions) -> bool {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path.display(), e);
+            return remove_tree(root, path, exclusions);
+        }
+    };
+    if exclusions.is_excluded(path, metadata.is_dir()) {
+        remove_tree(root, path, exclusions)
+    } else if metadata.is_dir() {
+        let (tracks, playlists, sheets, unplayable) = scan(
+            &root.root.path,
+            path,
+            exclusions,
+            &root.tracks,
+            &root.unplayable,
+        );
+        let changed = !tracks.is_empty();
+        root.tracks.extend(tracks);
+        root.playlists.extend(playlists);
+        root.unplayable.extend(unplayable);
+        for (file, sheets) in sheets {
+            root.cue_sheets.entry(file).or_default().extend(sheets);
+        }
+        changed
+    } else if PlaylistFile::is_playlist(path) {
+        match read_playlist(&root.root.path, path) {
+            Some(playlist) => {
+                root.playlists.insert(path.into(), Arc::new(playlist));
+            }
+            None => {
+                root.playlists.remove(path);
+            }
+        }
+        false
+    } else if CueSheet::is_cue_sheet(path) {
+        update_cue_sheet(root, path, exclusions)
+    } else if archive::is_archive(path) {
+        let members_root = archive::members_root(path);
+        let (mut probed, mut skipped) = (0, 0);
+        let mut unplayable = Unplayable::new();
+        let tracks = read_archive(
+            path,
+            &metadata,
+            &root.tracks,
+            &root.unplayable,
+            &mut unplayable,
+            &mut probed,
+            &mut skipped,
+        );
+        let changed = remove_tree(root, &members_root, exclusions) || probed > 0;
+        root.tracks.extend(tracks);
+        root.unplayable.extend(unplayable);
+        changed
+    } else {
+        let sheets = root.cue_sheets.get(path).cloned().unwrap_or_default();
+        if sheets.is_empty() {
+            update_file(root, path, &metadata)
+        } else {
+            let mut changed = false;
+            for sheet in sheets {
+                changed |= update_cue_sheet(root, &sheet, exclusions);
+            }
+            changed
+        }
+    }
+}
+
+fn update_file(root: &mut LibraryRoot, path: &Path, metadata: &Metadata) -> bool {
+    let stamp = stamp(metadata);
+    match root.tracks.get(path) {
+        Some(track) if track.is_unchanged(metadata) => false,
+        _ if root.unplayable.get(path) == Some(&stamp) => false,
+        _ => {
+            let path: Arc<Path> = path.into();
+            match probe(path.clone(), metadata) {
+                Some(track) => {
+                    root.unplayable.remove(&path);
+                    root.tracks.insert(path, Arc::new(track));
+                    true
+                }
+                None => {
+                    root.unplayable.insert(path.clone(), stamp);
+                    root.tracks.remove(&path).is_some()
+                }
+            }
+        }
+    }
+}
+
+fn remove_tree(root: &mut LibraryRoot, path: &Path, exclusions: &Exclusions) -> bool {
+    let members_removed =
+        archive::is_archive(path) && remove_tree(root, &archive::members_root(path), exclusions);
+    root.playlists
+        .retain(|playlist, _| !playlist.starts_with(path));
+    // Sheets elsewhere that split up files going away lose those tracks
+    let sheets: BTreeSet<_> = root
+        .cue_sheets
+        .range::<Path, _>((Bound::Included(path), Bound::Unbounded))
+        .take_while(|(file, _)| file.starts_with(path))
+        .flat_map(|(_, sheets)| sheets.iter().filter(|sheet| !sheet.starts_with(path)))
+        .cloned()
+        .collect();
+    forget_cue_sheets(root, path);
+    let unplayable: Vec<_> = root
+        .unplayable
+        .range::<Path, _>((Bound::Included(path), Bound::Unbounded))
+        .map(|(file, _)| file)
+        .take_while(|file| file.starts_with(path))
+        .cloned()
+        .collect();
+    for file in unplayable {
+        root.unplayable.remove(&file);
+    }
+    let doomed: Vec<_> = root
+        .tracks
+        .range::<Path, _>((Bound::Included(path), Bound::Unbounded))
+        .map(|(_, track)| track)
+        .take_while(|track| track.path.starts_with(path))
+        .cloned()
+        .collect();
+    for track in &doomed {
+        root.tracks.remove(&track.path);
+    }
+    // Files that were split up by a CUE sheet that has gone are playable on their own again
+    let released: BTreeSet<_> = doomed
+        .iter()
+        .map(|track| track.file())
+        .filter(|file| !file.starts_with(path))
+        .collect();
+    for file in released {
+        if file.exists() {
+            update(root, file, exclusions);
+        }
+    }
+    let mut changed = members_removed || !doomed.is_empty();
+    for sheet in sheets {
+        changed |= update_cue_sheet(root, &sheet, exclusions);
+    }
+    changed
+}
+
 pub async fn create_scanner(
-    root_path: PathBuf,
+    roots: Vec<Root>,
+    excludes: &[String],
+    cache_path: Option<PathBuf>,
     exit: &broadcast::Sender<()>,
-) -> Result<SongList, async_watcher::error::Error> {
-    let songs = Arc::new(RwLock::new(scan(&root_path)));
-    {
+) -> Result<(SongList, JoinHandle<()>), Box<dyn std::error::Error>> {
+    let (cache, unplayable_cache) = cache_path.as_deref().map(cache::load).unwrap_or_default();
+    let mut library = Library {
+        roots: Vec::new(),
+        duplicates: Default::default(),
+        generation: 0,
+    };
+    let mut all_exclusions = Vec::new();
+    for root in roots {
+        let exclusions = Exclusions::new(root.path.clone(), excludes)?;
+        let (tracks, playlists, cue_sheets, unplayable) = scan(
+            &root.path,
+            &root.path,
+            &exclusions,
+            &cache,
+            &unplayable_cache,
+        );
+        all_exclusions.push(exclusions);
+        library.roots.push(LibraryRoot {
+            root,
+            tracks,
+            playlists,
+            cue_sheets,
+            unplayable,
+        });
+    }
+    drop(cache);
+    drop(unplayable_cache);
+    if let Some(cache_path) = &cache_path {
+        cache::save(cache_path, &library);
+    }
+    let watch_roots: Vec<_> = library
+        .roots
+        .iter()
+        .map(|root| root.root.path.clone())
+        .zip(all_exclusions)
+        .collect();
+    let songs = Arc::new(RwLock::new(library));
+    let mut watchers = Vec::new();
+    for (index, (root_path, exclusions)) in watch_roots.into_iter().enumerate() {
+        watchers.push(watch(index, root_path, exclusions, songs.clone(), exit).await?);
+    }
+    let handle = {
         let songs = songs.clone();
-        let mut exit_rx = exit.subscribe();
-        let (mut debouncer, mut file_events) =
-            AsyncDebouncer::new_with_channel(Duration::from_secs(1), Some(Duration::from_secs(1)))
-                .await?;
-        debouncer
-            .watcher()
-            .watch(&root_path, RecursiveMode::Recursive)
-            .expect("Failed to scan directory");
         tokio::spawn(async move {
-            use async_watcher::notify::Error as NotifyError;
-            enum WatcherEvent {
-                Files(Option<Result<Vec<DebouncedEvent>, Vec<NotifyError>>>),
-                Exit,
-                Rescan,
-            }
-            let Ok(hup) = tokio::signal::unix::signal(SignalKind::hangup()) else {
-                eprintln!("Couldn't bind to SIGHUP");
-                return;
+            let mut dirty = false;
+            for watcher in watchers {
+                match watcher.await {
+                    Ok(changed) => dirty |= changed,
+                    Err(e) => eprintln!("Failed to stop file watcher: {}", e),
+                }
+            }
+            if dirty && let Some(cache_path) = cache_path {
+                cache::save(&cache_path, &*songs.read().await);
+            }
+        })
+    };
+    Ok((songs, handle))
+}
+
+async fn watch(
+    index: usize,
+    root_path: PathBuf,
+    exclusions: Exclusions,
+    songs: SongList,
+    exit: &broadcast::Sender<()>,
+) -> Result<JoinHandle<bool>, async_watcher::error::Error> {
+    let mut exit_rx = exit.subscribe();
+    let (mut debouncer, mut file_events) =
+        AsyncDebouncer::new_with_channel(Duration::from_secs(1), Some(Duration::from_secs(1)))
+            .await?;
+    debouncer
+        .watcher()
+        .watch(&root_path, RecursiveMode::Recursive)
+        .expect("Failed to scan directory");
+    let exclusions = Arc::new(exclusions);
+    Ok(tokio::spawn(async move {
+        use async_watcher::notify::Error as NotifyError;
+        let _debouncer = debouncer;
+        let mut dirty = false;
+        enum WatcherEvent {
+            Files(Option<Result<Vec<DebouncedEvent>, Vec<NotifyError>>>),
+            Exit,
+            Rescan,
+        }
+        let Ok(hup) = tokio::signal::unix::signal(SignalKind::hangup()) else {
+            eprintln!("Couldn't bind to SIGHUP");
+            return false;
+        };
+        let mut hup = Box::pin(hup);
+        loop {
+            let event = tokio::select! {
+                e = file_events.recv() => WatcherEvent::Files(e),
+                _ = exit_rx.recv() => WatcherEvent::Exit,
+                _ = hup.recv() => WatcherEvent::Rescan,
             };
-            let mut hup = Box::pin(hup);
-            loop {
-                let event = tokio::select! {
-                    e = file_events.recv() => WatcherEvent::Files(e),
-                    _ = exit_rx.recv() => WatcherEvent::Exit,
-                    _ = hup.recv() => WatcherEvent::Rescan,
-                };
-                match event {
-                    WatcherEvent::Exit | WatcherEvent::Files(None) => break,
-                    WatcherEvent::Rescan => {
-                        let mut songs = songs.write().await;
-                        *songs = scan(&root_path);
-                    }
-                    WatcherEvent::Files(Some(Ok(events))) => {
-                        let mut songs = songs.write().await;
-                        for DebouncedEvent { path, event, .. } in events {
-                            match event.kind {
-                                EventKind::Any => {}
-                                EventKind::Access(_) => {}
-                                EventKind::Create(_) => {
-                                    songs.insert(path.into());
-                                }
-                                EventKind::Modify(_) => {}
-                                EventKind::Remove(_) => {
-                                    songs.remove(path.as_path());
-                                }
-                                EventKind::Other => {
-                                    eprintln!(
-                                        "Got an other event for {}. Triggering rescan.",
-                                        path.display()
-                                    );
-                                    *songs = scan(&root_path)
-                                }
+            let mut changed = false;
+            match event {
+                WatcherEvent::Exit | WatcherEvent::Files(None) => break,
+                WatcherEvent::Rescan => {
+                    changed = change_root(&songs, index, &exclusions, |root, exclusions| {
+                        rescan(root, exclusions);
+                        true
+                    })
+                    .await;
+                }
+                WatcherEvent::Files(Some(Ok(events))) => {
+                    changed = change_root(&songs, index, &exclusions, move |root, exclusions| {
+                        apply_events(root, events, exclusions)
+                    })
+                    .await;
+                }
+                WatcherEvent::Files(Some(Err(errors))) => {
+                    for Error { kind, paths } in errors {
+                        match kind {
+                            ErrorKind::Generic(e) => {
+                                eprintln!("Error watching files: {}", e);
                             }
-                        }
-                    }
-                    WatcherEvent::Files(Some(Err(errors))) => {
-                        for Error { kind, paths } in errors {
-                            match kind {
-                                ErrorKind::Generic(e) => {
-                                    eprintln!("Error watching files: {}", e);
-                                }
-                                ErrorKind::Io(e) => {
-                                    eprintln!("Error watching files: {}", e)
-                                }
-                                ErrorKind::PathNotFound => {
-                                    let mut songs = songs.write().await;
-                                    for path in paths {
-                                        songs.remove(path.as_path());
-                                    }
-                                }
-                                ErrorKind::WatchNotFound => {
-                                    eprintln!("Watch not found");
-                                }
-                                ErrorKind::InvalidConfig(_) => {
-                                    eprintln!("Invalid config");
-                                }
-                                ErrorKind::MaxFilesWatch => {
-                                    eprintln!("Watching too many files")
-                                }
+                            ErrorKind::Io(e) => {
+                                eprintln!("Error watching files: {}", e)
+                            }
+                            ErrorKind::PathNotFound => {
+                                changed |= change_root(
+                                    &songs,
+                                    index,
+                                    &exclusions,
+                                    move |root, exclusions| {
+                                        let mut changed = false;
+                                        for path in paths {
+                                            changed |= remove_tree(root, &path, exclusions);
+                                        }
+                                        changed
+                                    },
+                                )
+                                .await;
+                            }
+                            ErrorKind::WatchNotFound => {
+                                eprintln!("Watch not found");
+                            }
+                            ErrorKind::InvalidConfig(_) => {
+                                eprintln!("Invalid config");
+                            }
+                            ErrorKind::MaxFilesWatch => {
+                                eprintln!("Watching too many files")
                             }
                         }
                     }
                 }
             }
-        });
+            if changed {
+                songs.write().await.generation += 1;
+                dirty = true;
+            }
+        }
+        dirty
+    }))
+}
+
+/// Changes a copy of a root on a blocking thread, since probing files is slow, so the library is only locked to
+/// put the changed root back
+async fn change_root(
+    songs: &SongList,
+    index: usize,
+    exclusions: &Arc<Exclusions>,
+    change: impl FnOnce(&mut LibraryRoot, &Exclusions) -> bool + Send + 'static,
+) -> bool {
+    let mut root = songs.read().await.roots[index].clone();
+    let exclusions = exclusions.clone();
+    match tokio::task::spawn_blocking(move || {
+        let changed = change(&mut root, &exclusions);
+        (root, changed)
+    })
+    .await
+    {
+        Ok((root, true)) => {
+            songs.write().await.roots[index] = root;
+            true
+        }
+        Ok((_, false)) => false,
+        Err(e) => {
+            eprintln!("Failed to update the library: {}", e);
+            false
+        }
+    }
+}
+
+fn apply_events(
+    root: &mut LibraryRoot,
+    events: Vec<DebouncedEvent>,
+    exclusions: &Exclusions,
+) -> bool {
+    if events
+        .iter()
+        .any(|event| Exclusions::is_ignore_file(&event.path))
+    {
+        eprintln!("Ignore file changed. Triggering rescan.");
+        rescan(root, exclusions);
+        return true;
+    }
+    let mut changed = false;
+    for DebouncedEvent { path, event, .. } in events {
+        match event.kind {
+            EventKind::Any => {}
+            EventKind::Access(_) => {}
+            EventKind::Create(_) => {
+                changed |= update(root, &path, exclusions);
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                // The debouncer keeps the first event per path, so the From and To events that precede this one
+                // normally win and this arm rarely runs. When it does, it is queued under both paths; act on the
+                // first.
+                if let [from, to] = &event.paths[..]
+                    && *from == path
+                {
+                    changed |= remove_tree(root, from, exclusions);
+                    changed |= update(root, to, exclusions);
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                changed |= remove_tree(root, &path, exclusions);
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                changed |= update(root, &path, exclusions);
+            }
+            EventKind::Modify(_) => {
+                if path.exists() {
+                    changed |= update(root, &path, exclusions);
+                } else {
+                    changed |= remove_tree(root, &path, exclusions);
+                }
+            }
+            EventKind::Remove(_) => {
+                changed |= remove_tree(root, &path, exclusions);
+            }
+            EventKind::Other => {
+                eprintln!(
+                    "Got an other event for {}. Triggering rescan.",
+                    path.display()
+                );
+                rescan(root, exclusions);
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A second of silence as 8 kHz mono WAV
+    fn wav() -> Vec<u8> {
+        let samples = 8000u32;
+        let mut data = Vec::new();
+        data.extend(b"RIFF");
+        data.extend((36 + samples * 2).to_le_bytes());
+        data.extend(b"WAVEfmt ");
+        data.extend(16u32.to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.extend(8000u32.to_le_bytes());
+        data.extend(16000u32.to_le_bytes());
+        data.extend(2u16.to_le_bytes());
+        data.extend(16u16.to_le_bytes());
+        data.extend(b"data");
+        data.extend((samples * 2).to_le_bytes());
+        data.resize(data.len() + samples as usize * 2, 0);
+        data
+    }
+
+    /// An empty directory for a test to fill
+    fn directory(test: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("radio-music-box-{}-{}", std::process::id(), test));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn library_root(path: &Path, exclusions: &Exclusions) -> LibraryRoot {
+        let mut root = LibraryRoot {
+            root: Root {
+                path: path.to_path_buf(),
+                weight: 1,
+            },
+            tracks: Tracks::new(),
+            playlists: Playlists::new(),
+            cue_sheets: CueSheets::new(),
+            unplayable: Unplayable::new(),
+        };
+        rescan(&mut root, exclusions);
+        root
+    }
+
+    fn track_names(root: &LibraryRoot) -> Vec<String> {
+        root.tracks
+            .keys()
+            .map(|path| {
+                path.strip_prefix(&root.root.path)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    const SHEET: &str = "FILE \"album.wav\" WAVE\n  TRACK 01 AUDIO\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    INDEX 01 00:00:40\n";
+
+    #[test]
+    fn cue_sheet_tracks_go_with_their_file() {
+        let path = directory("cue-delete");
+        std::fs::write(path.join("album.wav"), wav()).unwrap();
+        std::fs::write(path.join("album.cue"), SHEET).unwrap();
+        let exclusions = Exclusions::new(path.clone(), &[]).unwrap();
+        let mut root = library_root(&path, &exclusions);
+        assert_eq!(track_names(&root), ["album.cue/01", "album.cue/02"]);
+        std::fs::remove_file(path.join("album.wav")).unwrap();
+        assert!(remove_tree(&mut root, &path.join("album.wav"), &exclusions));
+        assert!(track_names(&root).is_empty());
+        // The sheet still names the file, so it splits it up again when it comes back
+        std::fs::write(path.join("album.wav"), wav()).unwrap();
+        assert!(update(&mut root, &path.join("album.wav"), &exclusions));
+        assert_eq!(track_names(&root), ["album.cue/01", "album.cue/02"]);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn cue_sheet_tracks_go_when_file_is_renamed() {
+        let path = directory("cue-rename");
+        std::fs::write(path.join("album.wav"), wav()).unwrap();
+        std::fs::write(path.join("album.cue"), SHEET).unwrap();
+        let exclusions = Exclusions::new(path.clone(), &[]).unwrap();
+        let mut root = library_root(&path, &exclusions);
+        std::fs::rename(path.join("album.wav"), path.join("renamed.wav")).unwrap();
+        remove_tree(&mut root, &path.join("album.wav"), &exclusions);
+        update(&mut root, &path.join("renamed.wav"), &exclusions);
+        assert_eq!(track_names(&root), ["renamed.wav"]);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn cue_sheets_only_split_files_in_the_library() {
+        let path = directory("cue-targets");
+        let root_path = path.join("root");
+        std::fs::create_dir_all(root_path.join("live")).unwrap();
+        std::fs::write(path.join("outside.wav"), wav()).unwrap();
+        std::fs::write(root_path.join("live/album.wav"), wav()).unwrap();
+        std::fs::write(root_path.join("album.wav"), wav()).unwrap();
+        std::fs::write(
+            root_path.join("outside.cue"),
+            SHEET.replace("album.wav", "../outside.wav"),
+        )
+        .unwrap();
+        std::fs::write(
+            root_path.join("live.cue"),
+            SHEET.replace("album.wav", "live/album.wav"),
+        )
+        .unwrap();
+        std::fs::write(
+            root_path.join("absolute.cue"),
+            SHEET.replace("album.wav", &root_path.join("album.wav").to_string_lossy()),
+        )
+        .unwrap();
+        let exclusions = Exclusions::new(root_path.clone(), &["live/".to_string()]).unwrap();
+        let mut root = library_root(&root_path, &exclusions);
+        assert_eq!(track_names(&root), ["absolute.cue/01", "absolute.cue/02"]);
+        assert!(!update(&mut root, &root_path.join("live.cue"), &exclusions));
+        assert_eq!(track_names(&root), ["absolute.cue/01", "absolute.cue/02"]);
+        std::fs::remove_dir_all(path).unwrap();
     }
-    Ok(songs)
 }
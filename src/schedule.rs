@@ -0,0 +1,192 @@
+use crate::playlist::{Mode, Source};
+use std::str::FromStr;
+
+const DAYS: [&str; 7] = [
+    "sunday",
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+];
+
+/// What to play at different times of the week
+pub struct Schedule {
+    pub entries: Vec<ScheduleEntry>,
+}
+
+pub struct ScheduleEntry {
+    /// Days of the week, starting from Sunday, that this applies to
+    pub days: [bool; 7],
+    /// Minutes after midnight; if the end is before the start, the range carries on into the next day
+    pub start: u32,
+    pub end: u32,
+    /// What to play, which is never another schedule
+    pub source: Source,
+    pub mode: Mode,
+}
+
+/// A time of day as `HH:MM`
+pub struct TimeOfDay(pub u32);
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+            .split_once(':')
+            .map(|(h, m)| (h.parse::<u32>(), m.parse::<u32>()))
+        {
+            // 24:00 ends a range at midnight
+            Some((Ok(hours), Ok(minutes)))
+                if hours < 24 && minutes < 60 || (hours, minutes) == (24, 0) =>
+            {
+                Ok(TimeOfDay(hours * 60 + minutes))
+            }
+            _ => Err(format!("Invalid time {}, expected HH:MM", s)),
+        }
+    }
+}
+
+/// Finds a day of the week, from Sunday, by its full or three letter name
+pub fn parse_day(s: &str) -> Result<usize, String> {
+    let lower = s.to_ascii_lowercase();
+    DAYS.iter()
+        .position(|day| *day == lower || day[..3] == lower)
+        .ok_or_else(|| format!("Unknown day {}", s))
+}
+
+impl Schedule {
+    /// The first entry that covers the current local time
+    pub fn active(&self) -> Option<usize> {
+        let (day, minute) = now();
+        self.active_at(day, minute)
+    }
+    /// The first entry that covers this minute of this day, from Sunday
+    fn active_at(&self, day: usize, minute: u32) -> Option<usize> {
+        self.entries.iter().position(|entry| {
+            if entry.start < entry.end {
+                entry.days[day] && (entry.start..entry.end).contains(&minute)
+            } else {
+                // Late on the listed day, or early the day after
+                (entry.days[day] && minute >= entry.start)
+                    || (entry.days[(day + 6) % 7] && minute < entry.end)
+            }
+        })
+    }
+}
+
+/// The local day of the week, from Sunday, and minutes after midnight
+fn now() -> (usize, u32) {
+    // SAFETY: time accepts a null pointer, and localtime_r only fills in the tm it is given, which is plain
+    // data that is valid when zeroed
+    let local = unsafe {
+        let time = libc::time(std::ptr::null_mut());
+        let mut local: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut local).is_null() {
+            return (0, 0);
+        }
+        local
+    };
+    (
+        local.tm_wday as usize % 7,
+        (local.tm_hour * 60 + local.tm_min) as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minutes(time: &str) -> Result<u32, String> {
+        time.parse().map(|TimeOfDay(minutes)| minutes)
+    }
+
+    /// An entry for these days, from Sunday, between two times
+    fn entry(days: &[usize], start: &str, end: &str) -> ScheduleEntry {
+        let mut listed = [false; 7];
+        for day in days {
+            listed[*day] = true;
+        }
+        ScheduleEntry {
+            days: listed,
+            start: minutes(start).unwrap(),
+            end: minutes(end).unwrap(),
+            source: Source::Library,
+            mode: Mode::Shuffle,
+        }
+    }
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(minutes("00:00"), Ok(0));
+        assert_eq!(minutes("7:05"), Ok(7 * 60 + 5));
+        assert_eq!(minutes("23:59"), Ok(23 * 60 + 59));
+        assert_eq!(minutes("24:00"), Ok(24 * 60));
+        for bad in [
+            "24:30",
+            "25:00",
+            "12:60",
+            "12",
+            "12:",
+            ":30",
+            "noon",
+            "-1:00",
+            "99999999999:00",
+        ] {
+            assert_eq!(
+                minutes(bad),
+                Err(format!("Invalid time {}, expected HH:MM", bad))
+            );
+        }
+    }
+
+    #[test]
+    fn parses_days() {
+        assert_eq!(parse_day("sunday"), Ok(0));
+        assert_eq!(parse_day("Mon"), Ok(1));
+        assert_eq!(parse_day("FRIDAY"), Ok(5));
+        assert_eq!(parse_day("sat"), Ok(6));
+        assert_eq!(parse_day("fri day"), Err("Unknown day fri day".to_string()));
+        assert_eq!(parse_day("su"), Err("Unknown day su".to_string()));
+    }
+
+    #[test]
+    fn finds_the_entry_for_the_time() {
+        let schedule = Schedule {
+            entries: vec![
+                entry(&[5], "13:00", "24:00"),
+                entry(&[1, 2, 3, 4, 5], "06:00", "12:00"),
+                entry(&[0, 1, 2, 3, 4, 5, 6], "00:00", "24:00"),
+            ],
+        };
+        let at = |day, time| schedule.active_at(day, minutes(time).unwrap());
+        assert_eq!(at(5, "13:00"), Some(0));
+        assert_eq!(at(5, "23:59"), Some(0));
+        assert_eq!(at(5, "12:59"), Some(2));
+        assert_eq!(at(1, "06:00"), Some(1));
+        assert_eq!(at(1, "11:59"), Some(1));
+        assert_eq!(at(1, "12:00"), Some(2));
+        assert_eq!(at(1, "05:59"), Some(2));
+        assert_eq!(at(6, "08:00"), Some(2));
+        assert_eq!(Schedule { entries: vec![] }.active_at(3, 0), None);
+    }
+
+    #[test]
+    fn carries_overnight_entries_into_the_next_day() {
+        let schedule = Schedule {
+            entries: vec![entry(&[5, 6], "22:00", "02:00")],
+        };
+        let at = |day, time| schedule.active_at(day, minutes(time).unwrap());
+        assert_eq!(at(4, "23:00"), None);
+        assert_eq!(at(5, "01:00"), None);
+        assert_eq!(at(5, "22:00"), Some(0));
+        assert_eq!(at(6, "01:59"), Some(0));
+        assert_eq!(at(6, "02:00"), None);
+        assert_eq!(at(6, "23:00"), Some(0));
+        // Saturday night carries on into Sunday morning
+        assert_eq!(at(0, "01:00"), Some(0));
+        assert_eq!(at(0, "22:00"), None);
+    }
+}
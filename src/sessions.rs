@@ -0,0 +1,174 @@
+use crate::playlist::Playlist;
+use futures::{Stream, StreamExt};
+use hyper::Request;
+use hyper::header::COOKIE;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+const COOKIE_NAME: &str = "session";
+/// Players that don't keep cookies can name their session in this query parameter instead
+const PARAMETER_NAME: &str = "session";
+
+/// Listeners' playlists, kept while they are connected and for a while after in case they come back
+#[derive(Clone)]
+pub struct Sessions(Arc<SessionsState>);
+
+struct SessionsState {
+    grace: Duration,
+    listeners: Mutex<HashMap<(String, String), Arc<Listener>>>,
+}
+
+/// One listener's playlist for one stream, shared by each connection they make to it
+struct Listener {
+    playlist: Mutex<Playlist>,
+    /// Increases with every connection; only the latest one gets songs
+    connection: AtomicU64,
+    /// When the latest connection ended, if it has
+    left: Mutex<Option<Instant>>,
+}
+
+/// A listener, as identified by their cookie or token
+pub struct Session {
+    sessions: Sessions,
+    id: String,
+    /// Whether the listener needs to be given a cookie
+    new: bool,
+    /// The stream requested, without any session token
+    pub stream: String,
+}
+
+/// A listener's playlist, which carries on in their next connection if this one drops
+pub struct SessionPlaylist {
+    listener: Arc<Listener>,
+    connection: u64,
+}
+
+impl Sessions {
+    pub fn new(grace: Duration) -> Self {
+        Sessions(Arc::new(SessionsState {
+            grace,
+            listeners: Mutex::new(HashMap::new()),
+        }))
+    }
+    /// The session from the request's token or cookie, or a new one
+    pub fn session<B>(&self, req: &Request<B>) -> Session {
+        let parameters: Vec<_> = form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+            .into_owned()
+            .collect();
+        let token = parameters
+            .iter()
+            .find(|(key, value)| key == PARAMETER_NAME && !value.is_empty())
+            .map(|(_, value)| value.clone());
+        let stream = if token.is_some() {
+            let query = form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(parameters.iter().filter(|(key, _)| key != PARAMETER_NAME))
+                .finish();
+            if query.is_empty() {
+                req.uri().path().to_string()
+            } else {
+                format!("{}?{}", req.uri().path(), query)
+            }
+        } else {
+            req.uri().to_string()
+        };
+        let cookie = req
+            .headers()
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(';'))
+            .find_map(|cookie| {
+                let (name, value) = cookie.trim().split_once('=')?;
+                (name == COOKIE_NAME && !value.is_empty()).then(|| value.to_string())
+            });
+        let new = token.is_none() && cookie.is_none();
+        Session {
+            sessions: self.clone(),
+            id: token
+                .or(cookie)
+                .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>())),
+            new,
+            stream,
+        }
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(String, String), Arc<Listener>>> {
+        let mut listeners = self.0.listeners.lock().expect("Failed to unlock sessions");
+        listeners.retain(|_, listener| {
+            listener
+                .lock_left()
+                .is_none_or(|left| left.elapsed() < self.0.grace)
+        });
+        listeners
+    }
+}
+
+impl Session {
+    /// The cookie to give a listener who doesn't have one yet
+    pub fn cookie(&self) -> Option<String> {
+        (self.new && !self.sessions.0.grace.is_zero()).then(|| {
+            format!(
+                "{}={}; Path=/; HttpOnly; SameSite=Lax",
+                COOKIE_NAME, self.id
+            )
+        })
+    }
+    /// Takes over the listener's playlist for this stream if they have one, or else starts this playlist
+    pub fn resume(self, playlist: Playlist) -> SessionPlaylist {
+        let new_listener = || {
+            Arc::new(Listener {
+                playlist: Mutex::new(playlist),
+                connection: AtomicU64::new(0),
+                left: Mutex::new(None),
+            })
+        };
+        let listener = if self.sessions.0.grace.is_zero() {
+            new_listener()
+        } else {
+            self.sessions
+                .lock()
+                .entry((self.id, self.stream))
+                .or_insert_with(new_listener)
+                .clone()
+        };
+        // Any older connection stops the next time it asks for a song
+        let connection = listener.connection.fetch_add(1, Ordering::Relaxed) + 1;
+        *listener.lock_left() = None;
+        SessionPlaylist {
+            listener,
+            connection,
+        }
+    }
+}
+
+impl Listener {
+    fn lock_left(&self) -> std::sync::MutexGuard<'_, Option<Instant>> {
+        self.left.lock().expect("Failed to unlock session")
+    }
+}
+
+impl Stream for SessionPlaylist {
+    type Item = <Playlist as Stream>::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.listener.connection.load(Ordering::Relaxed) != self.connection {
+            return Poll::Ready(None);
+        }
+        self.listener
+            .playlist
+            .lock()
+            .expect("Failed to unlock session playlist")
+            .poll_next_unpin(cx)
+    }
+}
+
+impl Drop for SessionPlaylist {
+    fn drop(&mut self) {
+        if self.listener.connection.load(Ordering::Relaxed) == self.connection {
+            *self.listener.lock_left() = Some(Instant::now());
+        }
+    }
+}
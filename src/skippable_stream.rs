@@ -0,0 +1,72 @@
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+
+/// Plays each song from a stream of songs in turn, abandoning the current one when skipped
+pub struct SkippableStream<S, F, D> {
+    songs: S,
+    play: F,
+    current: Option<D>,
+    skips_seen: u64,
+    skips: Arc<AtomicU64>,
+}
+#[derive(Clone)]
+pub struct Skip(Arc<AtomicU64>);
+
+impl<S, F, D> SkippableStream<S, F, D> {
+    pub fn new(songs: S, play: F) -> (Self, Skip) {
+        let skips = Arc::new(AtomicU64::new(0));
+        (
+            SkippableStream {
+                songs,
+                play,
+                current: None,
+                skips_seen: 0,
+                skips: skips.clone(),
+            },
+            Skip(skips),
+        )
+    }
+}
+
+impl<S: Stream + Unpin, F: FnMut(S::Item) -> D + Unpin, D: Stream + Unpin> Stream
+    for SkippableStream<S, F, D>
+{
+    type Item = D::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let SkippableStream {
+            songs,
+            play,
+            current,
+            skips_seen,
+            skips,
+        } = self.get_mut();
+        let skipped = skips.load(Ordering::Relaxed);
+        if skipped != *skips_seen {
+            *skips_seen = skipped;
+            *current = None;
+        }
+        loop {
+            if let Some(song) = current.as_mut() {
+                match song.poll_next_unpin(cx) {
+                    Poll::Ready(None) => *current = None,
+                    result => return result,
+                }
+            }
+            match songs.poll_next_unpin(cx) {
+                Poll::Ready(Some(song)) => *current = Some(play(song)),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl Skip {
+    pub fn skip(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
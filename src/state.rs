@@ -0,0 +1,193 @@
+use serde::de::DeserializeOwned;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Once};
+use std::time::Duration;
+
+/// How long a change waits to be written, so a burst of changes is saved once
+const SAVE_DELAY: Duration = Duration::from_secs(5);
+
+pub fn default_directory() -> Option<PathBuf> {
+    let state = match std::env::var_os("XDG_STATE_HOME") {
+        Some(state) if !state.is_empty() => PathBuf::from(state),
+        _ => PathBuf::from(std::env::var_os("HOME")?)
+            .join(".local")
+            .join("state"),
+    };
+    Some(state.join("radio-music-box"))
+}
+
+pub fn read<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                eprintln!("Failed to open {}: {}", path.display(), e);
+            }
+            return None;
+        }
+    };
+    match serde_json::from_reader(BufReader::new(file)) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+pub fn write<T: Serialize>(path: &Path, value: &T) {
+    if let Err(e) = serde_json::to_vec(value)
+        .map_err(Into::into)
+        .and_then(|contents| write_atomically(path, &contents))
+    {
+        eprintln!("Failed to write {}: {}", path.display(), e);
+    }
+}
+
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temporary = path.with_extension("tmp");
+    let mut output = BufWriter::new(File::create(&temporary)?);
+    output.write_all(contents)?;
+    output.flush()?;
+    drop(output);
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// A value kept in memory and saved from a background thread shortly after it changes, so nothing waits on the
+/// disk
+pub struct Persisted<T>(Arc<PersistedState<T>>);
+
+struct PersistedState<T> {
+    path: Option<PathBuf>,
+    value: Mutex<T>,
+    /// Whether there are changes waiting to be saved
+    dirty: Mutex<bool>,
+    /// Held while saving, so a flush can't race the background thread
+    saving: Mutex<()>,
+}
+
+impl<T> Clone for Persisted<T> {
+    fn clone(&self) -> Self {
+        Persisted(self.0.clone())
+    }
+}
+
+impl<T: Serialize + Send + 'static> Persisted<T> {
+    /// Keeps a value that is saved to this file, if there is one
+    pub fn new(path: Option<PathBuf>, value: T) -> Self {
+        Persisted(Arc::new(PersistedState {
+            path,
+            value: Mutex::new(value),
+            dirty: Mutex::new(false),
+            saving: Mutex::new(()),
+        }))
+    }
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.0.value.lock().expect("Failed to unlock saved state")
+    }
+    /// Schedules a save
+    pub fn changed(&self) {
+        if self.0.path.is_some() && !std::mem::replace(&mut *self.0.dirty(), true) {
+            queue(self.0.clone());
+        }
+    }
+    /// Writes any changes not yet saved
+    pub fn save(&self) {
+        self.0.save();
+    }
+}
+
+impl<T> PersistedState<T> {
+    fn dirty(&self) -> MutexGuard<'_, bool> {
+        self.dirty.lock().expect("Failed to unlock saved state")
+    }
+}
+
+trait Save: Send + Sync {
+    fn save(&self);
+}
+
+impl<T: Serialize + Send> Save for PersistedState<T> {
+    fn save(&self) {
+        let _saving = self.saving.lock().expect("Failed to unlock saved state");
+        let Some(path) = &self.path else {
+            return;
+        };
+        if !std::mem::take(&mut *self.dirty()) {
+            return;
+        }
+        // Only the copy in memory is made while holding the value, so readers aren't kept waiting on the disk
+        let contents =
+            serde_json::to_vec(&*self.value.lock().expect("Failed to unlock saved state"));
+        if let Err(e) = contents
+            .map_err(Into::into)
+            .and_then(|contents| write_atomically(path, &contents))
+        {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Values waiting for the background thread to save them
+static PENDING: Mutex<Vec<Arc<dyn Save>>> = Mutex::new(Vec::new());
+static PENDING_ADDED: Condvar = Condvar::new();
+static SAVER: Once = Once::new();
+
+fn queue(save: Arc<dyn Save>) {
+    SAVER.call_once(|| {
+        std::thread::spawn(|| {
+            loop {
+                let mut pending = PENDING.lock().expect("Failed to unlock pending saves");
+                while pending.is_empty() {
+                    pending = PENDING_ADDED
+                        .wait(pending)
+                        .expect("Failed to wait for state changes");
+                }
+                drop(pending);
+                std::thread::sleep(SAVE_DELAY);
+                let pending =
+                    std::mem::take(&mut *PENDING.lock().expect("Failed to unlock pending saves"));
+                for save in pending {
+                    save.save();
+                }
+            }
+        });
+    });
+    PENDING
+        .lock()
+        .expect("Failed to unlock pending saves")
+        .push(save);
+    PENDING_ADDED.notify_one();
+}
+
+/// Records about files, looked up by path but saved as a list, since each record names its file itself
+pub struct ByPath<T>(pub BTreeMap<Arc<Path>, T>);
+
+impl<T> Deref for ByPath<T> {
+    type Target = BTreeMap<Arc<Path>, T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for ByPath<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T: Serialize> Serialize for ByPath<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.values())
+    }
+}
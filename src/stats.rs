@@ -0,0 +1,113 @@
+use crate::state;
+use crate::state::{ByPath, Persisted};
+use crate::track::Track;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+pub const MAX_RATING: u8 = 5;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct TrackStats {
+    pub path: Arc<Path>,
+    pub rating: Option<u8>,
+    pub plays: u32,
+    pub last_played: Option<u64>,
+}
+
+/// How strongly each statistic sways weighted selection
+#[derive(Clone, Copy, Debug)]
+pub struct Weighting {
+    /// The weight is multiplied by the rating raised to this power; unrated songs count as the middle rating
+    pub rating_exponent: f64,
+    /// The weight is divided by one more than the play count raised to this power
+    pub plays_exponent: f64,
+    /// A song that has just played has no weight, recovering half of it every this many hours
+    pub recency_half_life: f64,
+}
+
+#[derive(Clone)]
+pub struct Stats {
+    weighting: Weighting,
+    stats: Persisted<ByPath<TrackStats>>,
+}
+
+impl Stats {
+    pub fn new(path: Option<PathBuf>, weighting: Weighting) -> Self {
+        let stats = path
+            .as_deref()
+            .and_then(state::read::<Vec<TrackStats>>)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|stats| (stats.path.clone(), stats))
+            .collect();
+        Stats {
+            weighting,
+            stats: Persisted::new(path, ByPath(stats)),
+        }
+    }
+    pub fn record_play(&self, song: &Arc<Path>) {
+        let mut stats = self.stats.lock();
+        let entry = Self::entry(&mut stats, song);
+        entry.plays += 1;
+        entry.last_played = Some(now());
+        self.stats.changed();
+    }
+    pub fn set_rating(&self, song: &Arc<Path>, rating: Option<u8>) {
+        let mut stats = self.stats.lock();
+        Self::entry(&mut stats, song).rating = rating;
+        self.stats.changed();
+    }
+    pub fn stats(&self) -> Vec<TrackStats> {
+        self.stats.lock().values().cloned().collect()
+    }
+    pub fn weights(&self, songs: &[Arc<Track>]) -> Vec<f64> {
+        let stats = self.stats.lock();
+        songs
+            .iter()
+            .map(|song| self.weight(stats.get(&song.path)))
+            .collect()
+    }
+    fn weight(&self, stats: Option<&TrackStats>) -> f64 {
+        let Weighting {
+            rating_exponent,
+            plays_exponent,
+            recency_half_life,
+        } = self.weighting;
+        let rating = stats
+            .and_then(|stats| stats.rating)
+            .map_or(MAX_RATING as f64 / 2.0, |rating| rating as f64);
+        let plays = stats.map_or(0, |stats| stats.plays);
+        let recency = match stats.and_then(|stats| stats.last_played) {
+            Some(last_played) if recency_half_life > 0.0 => {
+                let hours = now().saturating_sub(last_played) as f64 / 3600.0;
+                1.0 - 0.5f64.powf(hours / recency_half_life)
+            }
+            _ => 1.0,
+        };
+        rating.powf(rating_exponent) * recency / (1.0 + plays as f64).powf(plays_exponent)
+    }
+    fn entry<'a>(
+        stats: &'a mut BTreeMap<Arc<Path>, TrackStats>,
+        song: &Arc<Path>,
+    ) -> &'a mut TrackStats {
+        stats.entry(song.clone()).or_insert_with(|| TrackStats {
+            path: song.clone(),
+            rating: None,
+            plays: 0,
+            last_played: None,
+        })
+    }
+    pub fn save(&self) {
+        self.stats.save();
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
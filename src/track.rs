@@ -0,0 +1,189 @@
+use crate::archive;
+use serde::{Deserialize, Serialize};
+use std::fs::Metadata;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use symphonia::core::errors::Error;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+
+const LOSSLESS_CODECS: &[&str] = &["alac", "flac", "wavpack"];
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Track {
+    pub path: Arc<Path>,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub year: Option<u32>,
+    pub duration: Option<Duration>,
+    pub codec: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub span: Option<Span>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Span {
+    pub file: Arc<Path>,
+    pub start: Duration,
+    pub end: Option<Duration>,
+}
+
+impl Track {
+    pub fn new(path: Arc<Path>, metadata: &Metadata) -> Self {
+        Track {
+            path,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            title: None,
+            artist: None,
+            album: None,
+            genre: None,
+            track_number: None,
+            disc_number: None,
+            year: None,
+            duration: None,
+            codec: None,
+            sample_rate: None,
+            span: None,
+        }
+    }
+    pub fn file(&self) -> &Arc<Path> {
+        self.span.as_ref().map_or(&self.path, |span| &span.file)
+    }
+    /// Orders copies of a recording: lossless first, then by sample rate, then by bit rate
+    pub fn quality(&self) -> (bool, u32, u64) {
+        let lossless = self
+            .codec
+            .as_deref()
+            .is_some_and(|codec| codec.starts_with("pcm") || LOSSLESS_CODECS.contains(&codec));
+        let bit_rate = match (self.span.is_some(), self.duration) {
+            (false, Some(duration)) if !duration.is_zero() => {
+                (self.size as f64 * 8.0 / duration.as_secs_f64()) as u64
+            }
+            _ => 0,
+        };
+        (lossless, self.sample_rate.unwrap_or(0), bit_rate)
+    }
+    pub fn slice(&self, path: Arc<Path>, span: Span) -> Track {
+        Track {
+            path,
+            duration: span
+                .end
+                .or(self.duration)
+                .map(|end| end.saturating_sub(span.start)),
+            span: Some(span),
+            ..self.clone()
+        }
+    }
+    /// The same song, but starting part way through
+    pub fn starting_at(&self, position: Duration) -> Track {
+        let (start, end) = self
+            .span
+            .as_ref()
+            .map_or((Duration::ZERO, None), |span| (span.start, span.end));
+        Track {
+            duration: self
+                .duration
+                .map(|duration| duration.saturating_sub(position)),
+            span: Some(Span {
+                file: self.file().clone(),
+                start: start + position,
+                end,
+            }),
+            ..self.clone()
+        }
+    }
+    pub fn is_unchanged(&self, metadata: &Metadata) -> bool {
+        self.size == metadata.len() && self.modified == metadata.modified().ok()
+    }
+    pub fn probe(path: Arc<Path>, metadata: &Metadata) -> Result<Self, Error> {
+        let file = archive::open(&path)?;
+        let mut track = Track::new(path, metadata);
+        let source = MediaSourceStream::new(file, Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = track.path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(extension);
+        }
+        let mut prober = symphonia::default::get_probe().format(
+            &hint,
+            source,
+            &Default::default(),
+            &Default::default(),
+        )?;
+        // Container metadata (e.g., ID3v2 ahead of an MP3 stream) comes first; the format's own tags override it
+        if let Some(revision) = prober.metadata.get().as_ref().and_then(|m| m.current()) {
+            track.apply_tags(revision);
+        }
+        if let Some(revision) = prober.format.metadata().current() {
+            track.apply_tags(revision);
+        }
+        let codec_params = &prober
+            .format
+            .default_track()
+            .ok_or(Error::Unsupported("no audio track"))?
+            .codec_params;
+        let codec = symphonia::default::get_codecs()
+            .get_codec(codec_params.codec)
+            .ok_or(Error::Unsupported("no decoder for audio track"))?;
+        track.codec = Some(codec.short_name.to_string());
+        track.sample_rate = Some(
+            codec_params
+                .sample_rate
+                .ok_or(Error::Unsupported("unknown sample rate"))?,
+        );
+        track.duration = match (codec_params.n_frames, codec_params.time_base) {
+            (Some(frames), Some(time_base)) => {
+                let time = time_base.calc_time(frames);
+                Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+            }
+            (Some(frames), None) => codec_params
+                .sample_rate
+                .map(|rate| Duration::from_secs_f64(frames as f64 / rate as f64)),
+            _ => None,
+        };
+        Ok(track)
+    }
+    fn apply_tags(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let Some(key) = tag.std_key else {
+                continue;
+            };
+            let value = tag.value.to_string();
+            let value = value.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+            if value.is_empty() {
+                continue;
+            }
+            match key {
+                StandardTagKey::TrackTitle => self.title = Some(value.to_string()),
+                StandardTagKey::Artist => self.artist = Some(value.to_string()),
+                StandardTagKey::Album => self.album = Some(value.to_string()),
+                StandardTagKey::Genre => self.genre = Some(value.to_string()),
+                StandardTagKey::TrackNumber => {
+                    // Track numbers are frequently written as "3/12"
+                    self.track_number = value.split('/').next().and_then(|n| n.trim().parse().ok())
+                }
+                StandardTagKey::DiscNumber => {
+                    self.disc_number = value.split('/').next().and_then(|n| n.trim().parse().ok())
+                }
+                StandardTagKey::Date
+                | StandardTagKey::ReleaseDate
+                | StandardTagKey::OriginalDate => {
+                    // Dates may be a bare year or a full "1969-07-20"
+                    self.year = value
+                        .get(..4)
+                        .and_then(|year| year.parse().ok())
+                        .or(self.year)
+                }
+                _ => {}
+            }
+        }
+    }
+}